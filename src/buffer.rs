@@ -0,0 +1,33 @@
+use kilo_ed::*;
+
+use crate::save::*;
+use crate::text::*;
+use crate::undo::*;
+
+/* one open file; the active buffer's state lives in Editor, the others are parked here */
+#[derive(Default)]
+pub struct Buffer {
+    pub filename: String,
+    pub rows: Text,
+    pub cursor: Position,
+    pub mark: Option<Position>,
+    pub rowoff: usize,
+    pub coloff: usize,
+    pub dirty: usize,
+    pub read_only: bool,
+    pub history: History,
+    pub swap_dirty: usize,
+    pub disk: Option<DiskState>,
+    pub declined_disk: Option<DiskState>,
+    pub syntax: Option<usize>, // index into hldb
+}
+
+impl Buffer {
+    pub fn display_name(&self) -> &str {
+        if self.filename.is_empty() {
+            "[No Name]"
+        } else {
+            &self.filename
+        }
+    }
+}
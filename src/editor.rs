@@ -1,17 +1,34 @@
+use std::collections::HashMap;
 use std::fmt::Display;
-use std::path::Path;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
+use config::Config;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use crossterm::{terminal, Result};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 use kilo_ed::*;
 
+use crate::buffer::*;
 use crate::editor_syntax::*;
+use crate::file_format::*;
 use crate::keyboard::*;
 use crate::options::*;
-use crate::row::*;
+use crate::prompt::*;
+use crate::row::grapheme_width;
+use crate::save::*;
 use crate::screen::*;
+use crate::search::*;
+use crate::swap::*;
+use crate::text::*;
+use crate::theme::*;
+use crate::timer::*;
+use crate::undo::*;
+use crate::window::*;
 
 enum KeypressResult {
     Continue,
@@ -22,12 +39,26 @@ enum KeypressResult {
 enum PromptKey {
     Enter,
     Escape,
-    Char(char),
+    Char,
+    Toggle(char),
     Next,
     Prev,
+    Complete,
 }
 
 const KILO_QUIT_TIMES: usize = 3;
+const MIN_WINDOW_WIDTH: u16 = 20;
+const MIN_WINDOW_HEIGHT: u16 = 4;
+const RESIZE_STEP: i16 = 5;
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+const SWAP_EDITS: usize = 50;
+// Each this many bytes of buffer stretches the swap interval (and edit count) by one more step
+const SWAP_SCALE_BYTES: usize = 256 * 1024;
+const MAX_SWAP_SCALE: usize = 15;
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+// Lines of the buffer searched for the match count between keystrokes
+const MATCH_COUNT_LINES: usize = 10_000;
 
 #[derive(Copy, Clone)]
 enum EditorKey {
@@ -37,6 +68,14 @@ enum EditorKey {
     Down,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+enum ReplaceChoice {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
 enum SearchDirection {
     Backward,
     Forward,
@@ -45,194 +84,865 @@ enum SearchDirection {
 pub struct Editor {
     filename: String,
     status_msg: String,
-    status_time: Instant,
     screen: Screen,
     keyboard: Keyboard,
     cursor: Position,
-    render_x: u16,
-    rows: Vec<Row>,
-    rowoff: u16,
-    coloff: u16,
+    mark: Option<Position>,
+    render_x: usize,
+    rows: Text,
+    rowoff: usize,
+    coloff: usize,
     dirty: usize,
+    read_only: bool,
+    history: History,
+    swap_dirty: usize, // `dirty` when the swap file was last written, 0 if there isn't one
+    swap_time: Instant,
+    swap_dir: Option<PathBuf>,
+    swaps: SwapWriter,
+    disk: Option<DiskState>, // the file as it was last read or written
+    declined_disk: Option<DiskState>, // a newer version the user chose not to reload
+    timers: TimerQueue,
+    clipboard: String,
     quit_times: usize,
-    last_match: Option<usize>,
+    last_match: Option<Position>,
     direction: SearchDirection,
-    saved_hl: Option<usize>,
+    search_mode: SearchMode,
+    prompt_hint: String,
+    prompt_info: String,
+    prompt_history: HashMap<PromptKind, PromptHistory>,
+    search_matcher: Option<Matcher>,
+    match_count: Option<MatchCount>, // while the search prompt is still counting
+    highlighted_rows: Vec<usize>,
     hldb: Vec<EditorSyntax>,
     syntax: Option<usize>, // index into hldb
+    buffers: Vec<Buffer>,  // the slot at `current` is empty while that buffer is active
+    current: usize,
+    windows: Vec<Window>, // the slot at `active_window` is stale while it has focus
+    active_window: usize,
+    layout: Layout,
     options: Options,
 }
 
 impl Editor {
-    pub fn with_file<P: AsRef<Path> + ToString>(options: Options, filename: P) -> Result<Self> {
-        let fn_string = filename.to_string();
-        let lines = std::fs::read_to_string(filename)
-            .expect("Unable to open file")
-            .split('\n')
-            .map(|x| x.into())
-            .collect::<Vec<String>>();
-        Editor::build(&lines, fn_string, options)
+    pub fn with_files<P: AsRef<Path> + ToString>(
+        options: Options,
+        filenames: &[P],
+    ) -> Result<Self> {
+        let mut ed = Editor::new(options)?;
+        for filename in filenames {
+            ed.open_file(filename)?;
+        }
+        ed.switch_buffer(0);
+        if !ed.filename.is_empty() && !Path::new(&ed.filename).exists() {
+            ed.set_status_message(format!("{} [New File]", ed.filename));
+        }
+        Ok(ed)
     }
 
     pub fn new(options: Options) -> Result<Self> {
-        Editor::build(&[], "", options)
+        Editor::build("", "", options)
     }
 
-    fn build<T: Into<String>>(data: &[String], filename: T, options: Options) -> Result<Self> {
-        let filename: String = filename.into();
-        let hldb = EditorSyntax::new();
-        let syntax = Editor::find_highlight(&hldb, filename.as_str());
-        let syntax_data = syntax.map(|idx| hldb[idx].clone());
-
+    fn build<T: Into<String>>(data: &str, filename: T, options: Options) -> Result<Self> {
         let mut ed = Self {
-            filename,
+            filename: String::new(),
             status_msg: String::from("HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = find"),
-            status_time: Instant::now(),
             screen: Screen::new(options)?,
             keyboard: Keyboard {},
             cursor: Position::default(),
-            rows: if data.is_empty() {
-                Vec::new()
-            } else {
-                let v = Vec::from(data);
-                let mut rows = Vec::new();
-                for row in v {
-                    rows.push(Row::new(row))
-                }
-                if rows.last().unwrap().len() == 0 {
-                    rows.pop();
-                }
-                rows
-            },
+            mark: None,
+            rows: Text::default(),
             rowoff: 0,
             coloff: 0,
             render_x: 0,
             dirty: 0,
+            read_only: false,
+            history: History::new(),
+            swap_dirty: 0,
+            swap_time: Instant::now(),
+            swap_dir: None,
+            swaps: SwapWriter::default(),
+            disk: None,
+            declined_disk: None,
+            timers: TimerQueue::default(),
+            clipboard: String::new(),
             quit_times: KILO_QUIT_TIMES,
             last_match: None,
             direction: SearchDirection::Forward,
-            saved_hl: None,
-            hldb,
-            syntax,
+            search_mode: SearchMode::default(),
+            prompt_hint: String::new(),
+            prompt_info: String::new(),
+            prompt_history: HashMap::new(),
+            search_matcher: None,
+            match_count: None,
+            highlighted_rows: Vec::new(),
+            hldb: EditorSyntax::new(),
+            syntax: None,
+            buffers: vec![Buffer::default()],
+            current: 0,
+            windows: vec![Window::default()],
+            active_window: 0,
+            layout: Layout::Window(0),
             options,
         };
 
-        let mut in_comment = false;
-        for r in ed.rows.iter_mut() {
-            r.update_syntax(in_comment, &syntax_data);
-            in_comment = r.open_comment;
-        }
+        let mut buffer = ed.new_buffer(data, filename.into());
+        buffer.read_only = options.read_only;
+        ed.load_buffer(buffer);
 
         Ok(ed)
     }
 
-    pub fn process_event(&mut self) -> bool {
-        let prev_dirty = self.dirty;
-        let event = self.keyboard.read();
-        match event {
-            Ok(c) => {
-                #[cfg(feature = "keystroke_log")]
-                eprintln!("{c:?}");
+    /* returns the contents of the file, or None if it doesn't exist yet */
+    fn read_file<P: AsRef<Path> + ToString>(filename: &P) -> Result<Option<Vec<u8>>> {
+        let fn_string = filename.to_string();
+        if filename.as_ref().is_dir() {
+            return Err(Error::other(format!("{fn_string}: is a directory")));
+        }
 
-                match c {
-                    InputEvent::Key(key) => match self.process_keypress(key) {
-                        KeypressResult::ExitEditor => return true,
-                        KeypressResult::Continue => self.quit_times = KILO_QUIT_TIMES,
-                        KeypressResult::Quitting => {}
-                    },
-                    InputEvent::Resize(col, row) => self.screen.resize(col, row),
-                    InputEvent::ScrollUp => {
-                        if self.cursor.y > self.rowoff {
-                            self.cursor.y = self.rowoff;
-                        } else {
-                            self.move_cursor(EditorKey::Up);
-                        }
-                    }
-                    InputEvent::ScrollDown => {
-                        let bounds = self.screen.bounds();
-                        let new_pos = (self.rowoff + bounds.y - 1).min(self.rows.len() as u16);
-                        if self.cursor.y < new_pos {
-                            self.cursor.y = new_pos;
-                        } else {
-                            self.move_cursor(EditorKey::Down);
-                        }
-                    }
+        match std::fs::read(filename) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::new(e.kind(), format!("{fn_string}: {e}"))),
+        }
+    }
+
+    fn new_buffer(&self, data: &str, filename: String) -> Buffer {
+        let mut rows = Text::new(data, None);
+        let syntax = Editor::find_highlight(&self.hldb, filename.as_str(), &rows);
+        rows.set_syntax(syntax.map(|idx| self.hldb[idx].clone()));
+
+        Buffer {
+            filename,
+            rows,
+            syntax,
+            ..Default::default()
+        }
+    }
+
+    /* opens a file in a new buffer (a missing file starts out empty) and makes it active */
+    pub fn open_file<P: AsRef<Path> + ToString>(&mut self, filename: &P) -> Result<()> {
+        let buffer = self.read_buffer(filename)?;
+        self.add_buffer(buffer);
+        Ok(())
+    }
+
+    /* a new buffer holding the file's contents (a missing file starts out empty) */
+    fn read_buffer<P: AsRef<Path> + ToString>(&mut self, filename: &P) -> Result<Buffer> {
+        let bytes = Editor::read_file(filename)?;
+        let disk = bytes
+            .as_ref()
+            .map(|bytes| DiskState::new(filename.as_ref(), bytes));
+        let bytes = bytes.unwrap_or_default();
+
+        let (text, encoding, malformed) = decode(&bytes, self.options.encoding.0);
+        let mut buffer = self.new_buffer(&text, filename.to_string());
+        buffer.rows.set_format(FileFormat {
+            encoding,
+            ..buffer.rows.format()
+        });
+        buffer.disk = disk;
+        // files we aren't allowed to write to open in view mode
+        buffer.read_only = self.options.read_only
+            || (buffer.disk.is_some()
+                && OpenOptions::new()
+                    .write(true)
+                    .open(filename.as_ref())
+                    .is_err());
+
+        if malformed {
+            self.set_status_message(format!(
+                "{}: not valid {}, bad bytes were replaced",
+                filename.to_string(),
+                encoding.name()
+            ));
+        }
+        Ok(buffer)
+    }
+
+    /* re-reads the active buffer's file, keeping the cursor on the same line */
+    fn reload(&mut self) {
+        let filename = self.filename.clone();
+        let buffer = match self.read_buffer(&filename) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                self.set_status_message(format!("Can't reload! {e}"));
+                return;
+            }
+        };
+
+        self.remove_swap();
+        self.rows = buffer.rows;
+        self.history = buffer.history;
+        self.disk = buffer.disk;
+        self.declined_disk = None;
+        self.dirty = 0;
+        self.mark = None;
+        self.cursor.y = self.cursor.y.min(self.rows.len());
+        self.cursor.x = self.cursor.x.min(self.current_row_len());
+        self.snap_cursor();
+        self.set_status_message(format!("Reloaded {filename}"));
+    }
+
+    /*
+     * offers to reload the active buffer when its file changes and it has no
+     * unsaved changes, returning whether it did
+     */
+    fn check_disk(&mut self) -> bool {
+        if self.dirty > 0 {
+            return false;
+        }
+
+        let filename = self.filename.clone();
+        let path = Path::new(&filename);
+        // don't ask again about a version that was already turned down
+        if let Some(declined) = self.declined_disk.as_mut() {
+            if declined.changed(path).is_none() {
+                return false;
+            }
+        }
+        let Some(state) = self.disk.as_mut().and_then(|disk| disk.changed(path)) else {
+            return false;
+        };
+        let question = format!("{filename} changed on disk. Reload it? (y/n)");
+        if self.read_choice(&question, "yn") == Some('y') {
+            self.reload();
+        } else {
+            // `disk` stays as it was, so saving still warns about the change
+            self.declined_disk = Some(state);
+        }
+        true
+    }
+
+    /* makes `buffer` the active one, keeping the current buffer open */
+    fn add_buffer(&mut self, buffer: Buffer) {
+        // an untouched scratch buffer is simply replaced
+        let scratch = self.filename.is_empty() && self.rows.is_empty() && self.dirty == 0;
+        if !(scratch && self.buffers.len() == 1) {
+            self.buffers[self.current] = self.take_buffer();
+            self.buffers.push(Buffer::default());
+            self.current = self.buffers.len() - 1;
+        }
+        self.load_buffer(buffer);
+    }
+
+    /* moves the active buffer's state out of the editor */
+    fn take_buffer(&mut self) -> Buffer {
+        self.update_swap(false);
+        Buffer {
+            filename: std::mem::take(&mut self.filename),
+            rows: std::mem::take(&mut self.rows),
+            cursor: self.cursor,
+            mark: self.mark.take(),
+            rowoff: self.rowoff,
+            coloff: self.coloff,
+            dirty: self.dirty,
+            read_only: self.read_only,
+            history: std::mem::take(&mut self.history),
+            swap_dirty: self.swap_dirty,
+            disk: self.disk,
+            declined_disk: self.declined_disk,
+            syntax: self.syntax,
+        }
+    }
+
+    fn load_buffer(&mut self, buffer: Buffer) {
+        self.filename = buffer.filename;
+        self.rows = buffer.rows;
+        self.cursor = buffer.cursor;
+        self.mark = buffer.mark;
+        self.rowoff = buffer.rowoff;
+        self.coloff = buffer.coloff;
+        self.dirty = buffer.dirty;
+        self.read_only = buffer.read_only;
+        self.history = buffer.history;
+        self.swap_dirty = buffer.swap_dirty;
+        self.disk = buffer.disk;
+        self.declined_disk = buffer.declined_disk;
+        self.syntax = buffer.syntax;
+    }
+
+    fn switch_buffer(&mut self, idx: usize) {
+        if idx == self.current || idx >= self.buffers.len() {
+            return;
+        }
+
+        self.activate_buffer(idx);
+        self.set_status_message(format!(
+            "Buffer {}/{}: {}",
+            idx + 1,
+            self.buffers.len(),
+            self.display_name()
+        ));
+    }
+
+    /* makes buffer `idx` the active one without saying so */
+    fn activate_buffer(&mut self, idx: usize) {
+        if idx == self.current {
+            return;
+        }
+        self.buffers[self.current] = self.take_buffer();
+        let buffer = std::mem::take(&mut self.buffers[idx]);
+        self.current = idx;
+        self.load_buffer(buffer);
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        if count == 1 {
+            self.set_status_message("No other buffers");
+            return;
+        }
+        self.switch_buffer(if forward {
+            (self.current + 1) % count
+        } else {
+            (self.current + count - 1) % count
+        });
+    }
+
+    fn close_buffer(&mut self) {
+        if self.dirty > 0
+            && self.read_choice("Buffer has unsaved changes. Close anyway? (y/n)", "yn")
+                != Some('y')
+        {
+            self.set_status_message("Close aborted");
+            return;
+        }
+        self.remove_swap();
+
+        if self.buffers.len() == 1 {
+            self.load_buffer(Buffer::default());
+            self.buffers[0] = Buffer::default();
+        } else {
+            let closed = self.current;
+            self.buffers.remove(closed);
+            self.current = closed.min(self.buffers.len() - 1);
+            let buffer = std::mem::take(&mut self.buffers[self.current]);
+            self.load_buffer(buffer);
+
+            // other windows showing the closed buffer move to the new one
+            for window in self.windows.iter_mut() {
+                if window.buffer == closed {
+                    *window = Window {
+                        buffer: self.current,
+                        ..Default::default()
+                    };
+                } else if window.buffer > closed {
+                    window.buffer -= 1;
                 }
             }
+        }
+        self.set_status_message(format!("Now editing {}", self.display_name()));
+    }
+
+    /* the directory that paths typed at the Open prompt are relative to */
+    fn base_dir(&self) -> PathBuf {
+        match Path::new(&self.filename).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    fn open_callback(&mut self, input: &mut String, event: PromptKey) {
+        self.prompt_info.clear();
+        if let PromptKey::Complete = event {
+            let (completed, candidates) = complete_path(&self.base_dir(), input);
+            *input = completed;
+            match candidates.len() {
+                0 => self.prompt_info = String::from("  (no matches)"),
+                1 => {}
+                _ => self.prompt_info = format!("  ({})", candidates.join(" ")),
+            }
+        }
+    }
+
+    fn open(&mut self) {
+        let path = match self.prompt(
+            PromptKind::Open,
+            "Open (Tab to complete)",
+            Some(Editor::open_callback),
+        ) {
+            Some(path) if !path.is_empty() => path,
+            _ => {
+                self.set_status_message("Open aborted");
+                return;
+            }
+        };
+
+        let base = self.base_dir();
+        let filename = if base == Path::new(".") {
+            path
+        } else {
+            base.join(path).to_string_lossy().to_string()
+        };
+
+        // switch to the file if it's already open
+        if filename == self.filename {
+            return;
+        }
+        if let Some(idx) = self.buffers.iter().position(|b| b.filename == filename) {
+            self.switch_buffer(idx);
+            return;
+        }
+
+        match self.open_file(&filename) {
+            Ok(()) if Path::new(&filename).exists() => {
+                self.set_status_message(format!("Opened {filename}"));
+                self.check_swap();
+            }
+            Ok(()) => self.set_status_message(format!("{filename} [New File]")),
+            Err(e) => self.set_status_message(format!("Can't open! {e}")),
+        }
+    }
+
+    fn list_buffers(&mut self) {
+        let list = (0..self.buffers.len())
+            .map(|idx| {
+                let (name, dirty) = if idx == self.current {
+                    (self.display_name(), self.dirty > 0)
+                } else {
+                    let buffer = &self.buffers[idx];
+                    (buffer.display_name(), buffer.dirty > 0)
+                };
+                format!("{}:{}{}", idx + 1, name, if dirty { "*" } else { "" })
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if let Some(choice) = self.prompt(PromptKind::Buffer, &format!("{list} | Switch to"), None)
+        {
+            match choice.trim().parse::<usize>() {
+                Ok(n) if n >= 1 && n <= self.buffers.len() => self.switch_buffer(n - 1),
+                _ => self.set_status_message(format!("No buffer '{choice}'")),
+            }
+        }
+    }
+
+    /* stashes the focused window's view so another window can take focus */
+    fn park_window(&mut self) {
+        self.windows[self.active_window] = Window {
+            buffer: self.current,
+            cursor: self.cursor,
+            mark: self.mark.take(),
+            rowoff: self.rowoff,
+            coloff: self.coloff,
+        };
+    }
+
+    fn focus_window(&mut self, id: usize) {
+        if id == self.active_window || id >= self.windows.len() {
+            return;
+        }
+
+        self.park_window();
+        let window = self.windows[id];
+        if window.buffer != self.current {
+            self.switch_buffer(window.buffer);
+        }
+        self.active_window = id;
+        self.cursor = window.cursor;
+        self.mark = window.mark;
+        self.rowoff = window.rowoff;
+        self.coloff = window.coloff;
+
+        // the buffer may have been edited through another window
+        self.cursor.y = self.cursor.y.min(self.rows.len());
+        self.snap_cursor();
+        self.mark = self.mark.filter(|mark| mark.row() < self.rows.len());
+    }
+
+    fn window_regions(&self) -> Vec<(usize, Rect)> {
+        let mut regions = Vec::new();
+        self.layout.arrange(
+            self.screen.text_area(),
+            self.min_window_size(),
+            &mut regions,
+            &mut Vec::new(),
+        );
+        regions
+    }
+
+    /* windows keep room for the line number gutter on top of the minimum */
+    fn min_window_size(&self) -> Size {
+        Size {
+            width: MIN_WINDOW_WIDTH + self.screen.gutter_width(),
+            height: MIN_WINDOW_HEIGHT,
+        }
+    }
+
+    fn split_window(&mut self, vertical: bool) {
+        let regions = self.window_regions();
+        let region = regions
+            .iter()
+            .find(|(id, _)| *id == self.active_window)
+            .map(|(_, region)| *region)
+            .unwrap_or_default();
+        let min = self.min_window_size();
+        if (vertical && region.width < min.width * 2 + 1)
+            || (!vertical && region.height < min.height * 2)
+        {
+            self.set_status_message("Not enough room to split this window");
+            return;
+        }
+
+        let new_id = self.windows.len();
+        self.windows.push(Window {
+            buffer: self.current,
+            cursor: self.cursor,
+            mark: None,
+            rowoff: self.rowoff,
+            coloff: self.coloff,
+        });
+        self.layout.split(self.active_window, new_id, vertical);
+        self.focus_window(new_id);
+    }
+
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.set_status_message("Can't close the only window");
+            return;
+        }
+
+        let closed = self.active_window;
+        self.layout.remove(closed);
+        self.windows.remove(closed);
+
+        // the closed window's view is thrown away rather than parked
+        let next = self.layout.windows()[0];
+        let window = self.windows[next];
+        if window.buffer != self.current {
+            self.buffers[self.current] = self.take_buffer();
+            let buffer = std::mem::take(&mut self.buffers[window.buffer]);
+            self.current = window.buffer;
+            self.load_buffer(buffer);
+        }
+        self.active_window = next;
+        self.cursor = window.cursor;
+        self.mark = window.mark;
+        self.rowoff = window.rowoff;
+        self.coloff = window.coloff;
+        self.cursor.y = self.cursor.y.min(self.rows.len());
+        self.snap_cursor();
+    }
+
+    fn cycle_window(&mut self) {
+        let order = self.layout.windows();
+        let pos = order
+            .iter()
+            .position(|id| *id == self.active_window)
+            .unwrap_or(0);
+        self.focus_window(order[(pos + 1) % order.len()]);
+    }
+
+    fn focus_neighbour(&mut self, direction: Direction) {
+        let regions = self.window_regions();
+        let from = regions.iter().find(|(id, _)| *id == self.active_window);
+        if let Some(id) = from.and_then(|(_, from)| neighbour(&regions, *from, direction)) {
+            self.focus_window(id);
+        }
+    }
+
+    fn resize_window(&mut self, delta: i16) {
+        if self.windows.len() == 1 {
+            self.set_status_message("Only one window");
+        } else if !self.layout.resize(
+            self.active_window,
+            delta,
+            self.screen.text_area(),
+            self.min_window_size(),
+        ) {
+            self.set_status_message("Can't resize this window any further");
+        }
+    }
+
+    fn unsaved_buffers(&self) -> usize {
+        let others = self
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|(idx, buffer)| *idx != self.current && buffer.dirty > 0)
+            .count();
+        others + usize::from(self.dirty > 0)
+    }
+
+    fn display_name(&self) -> &str {
+        if self.filename.is_empty() {
+            "[No Name]"
+        } else {
+            &self.filename
+        }
+    }
+
+    /* waits for input, giving up with None when the next timer is due */
+    fn next_event(&mut self) -> Option<InputEvent> {
+        let event = match self.timers.next_timeout() {
+            Some(timeout) => self.keyboard.read_timeout(timeout),
+            None => self.keyboard.read().map(Some),
+        };
+        match event {
+            Ok(event) => event,
             Err(e) => {
                 self.die("Unable to read from keyboard", e);
+                None
             }
         }
+    }
 
-        // if the text changed, then update the syntax highlighting
-        if prev_dirty < self.dirty {
-            let prev_row_status =
-                self.cursor.y > 0 && self.rows[(self.cursor.y - 1) as usize].open_comment;
-            self.update_remaining_lines(self.cursor.y as usize, prev_row_status);
+    /* handles one input event, returning true when it's time to exit */
+    pub fn process_event(&mut self, event: InputEvent) -> bool {
+        match event {
+            InputEvent::Key(key) => match self.process_keypress(key) {
+                KeypressResult::ExitEditor => return true,
+                KeypressResult::Continue => self.quit_times = KILO_QUIT_TIMES,
+                KeypressResult::Quitting => {}
+            },
+            InputEvent::Resize(col, row) => self.screen.resize(col, row),
+            InputEvent::FocusLost if self.options.autosave.on_focus_lost => self.autosave(),
+            InputEvent::FocusLost => {}
+            InputEvent::FocusGained => {
+                self.check_disk();
+            }
+            InputEvent::ScrollUp => {
+                if self.cursor.y > self.rowoff {
+                    self.cursor.y = self.rowoff;
+                } else {
+                    self.move_cursor(EditorKey::Up);
+                }
+            }
+            InputEvent::ScrollDown => {
+                let bounds = self.screen.bounds();
+                let new_pos = (self.rowoff + bounds.y - 1).min(self.rows.len());
+                if self.cursor.y < new_pos {
+                    self.cursor.y = new_pos;
+                } else {
+                    self.move_cursor(EditorKey::Down);
+                }
+            }
         }
 
+        // the idle delay starts over with every event
+        if let Some(idle) = self.options.autosave.idle {
+            self.timers.schedule(Timer::Autosave, idle);
+        }
+        self.update_swap(false);
+        if self.dirty != self.swap_dirty {
+            self.timers.schedule_once(Timer::Swap, self.swap_interval());
+        }
         false
     }
 
+    /* runs the timers that are due, returning whether the screen needs redrawing */
+    fn run_timers(&mut self) -> bool {
+        let mut redraw = false;
+        for timer in self.timers.take_due() {
+            redraw |= match timer {
+                Timer::StatusExpiry => {
+                    self.status_msg.clear();
+                    true
+                }
+                Timer::Autosave => {
+                    self.autosave();
+                    true
+                }
+                Timer::Swap => {
+                    self.update_swap(true);
+                    false
+                }
+                Timer::DiskCheck => {
+                    self.timers.schedule(Timer::DiskCheck, DISK_CHECK_INTERVAL);
+                    self.check_disk()
+                }
+            };
+        }
+        redraw
+    }
+
     pub fn start(&mut self) -> Result<()> {
         terminal::enable_raw_mode()?;
         self.screen.capture_mouse()?;
+        if self.options.autosave.on_focus_lost {
+            self.screen.report_focus()?;
+        }
+        self.check_all_swaps();
+        self.timers.schedule(Timer::DiskCheck, DISK_CHECK_INTERVAL);
 
+        let mut redraw = true;
         loop {
-            if let Err(e) = self.refresh_screen() {
-                self.die("unable to refresh screen", e);
-            }
-            self.screen
-                .move_to(&self.cursor, self.render_x, self.rowoff, self.coloff)?;
-            self.screen.flush()?;
-            if self.process_event() {
-                break;
+            if redraw {
+                if let Err(e) = self.refresh_screen() {
+                    self.die("unable to refresh screen", e);
+                }
+                self.screen
+                    .move_to(&self.cursor, self.render_x, self.rowoff, self.coloff)?;
+                self.screen.flush()?;
             }
+
+            redraw = match self.next_event() {
+                Some(event) => {
+                    #[cfg(feature = "keystroke_log")]
+                    eprintln!("{event:?}");
+
+                    if self.process_event(event) {
+                        break;
+                    }
+                    true
+                }
+                None => self.run_timers(),
+            };
+        }
+        self.remove_all_swaps();
+        if self.options.autosave.on_focus_lost {
+            self.screen.stop_reporting_focus()?;
         }
         self.screen.release_mouse()?;
         terminal::disable_raw_mode()
     }
 
     pub fn refresh_screen(&mut self) -> Result<()> {
+        let mut regions = Vec::new();
+        let mut separators = Vec::new();
+        self.layout.arrange(
+            self.screen.text_area(),
+            self.min_window_size(),
+            &mut regions,
+            &mut separators,
+        );
+
+        self.screen.clear()?;
+        for separator in separators {
+            self.screen.draw_separator(separator)?;
+        }
+
+        // Draw the focused window last so the cursor lines up with its rows
+        let mut active_region = self.screen.text_area();
+        for (id, region) in regions {
+            if id == self.active_window {
+                active_region = region;
+            } else {
+                self.draw_window(id, region)?;
+            }
+        }
+
+        self.screen.set_region(active_region);
         self.scroll();
-        let row_count = self.screen.clear(&self.rows, self.rowoff)?;
+        let height = self.screen.height() as usize;
+        let row_count = self
+            .screen
+            .visible_rows(&self.rows.visible(self.rowoff, height));
         if self.cursor.y > self.rowoff + row_count {
             self.rowoff = self.cursor.y - row_count;
         }
+        self.highlight_search_matches();
+
+        let selection = self.selection();
+        self.screen.draw_rows(
+            &self.rows.visible(self.rowoff, height),
+            self.rowoff,
+            self.coloff,
+            self.cursor.y,
+            selection,
+        )?;
+        let (left, right) = self.status_text(
+            self.current,
+            self.display_name(),
+            &self.rows,
+            &Editor::buffer_flags(self.dirty, self.read_only),
+            self.syntax,
+            self.cursor.y,
+        );
+        self.screen.draw_status_bar(left, right, true)?;
+        self.screen.draw_message_bar(&self.status_msg)
+    }
 
-        self.screen
-            .draw_rows(&self.rows, self.rowoff, self.coloff, self.cursor.y)?;
+    /* draws a window that doesn't have focus */
+    fn draw_window(&mut self, id: usize, region: Rect) -> Result<()> {
+        let window = self.windows[id];
+        let (left, right) = if window.buffer == self.current {
+            self.status_text(
+                window.buffer,
+                self.display_name(),
+                &self.rows,
+                &Editor::buffer_flags(self.dirty, self.read_only),
+                self.syntax,
+                window.cursor.y,
+            )
+        } else {
+            let buffer = &self.buffers[window.buffer];
+            self.status_text(
+                window.buffer,
+                buffer.display_name(),
+                &buffer.rows,
+                &Editor::buffer_flags(buffer.dirty, buffer.read_only),
+                buffer.syntax,
+                window.cursor.y,
+            )
+        };
 
-        if !self.status_msg.is_empty() && self.status_time.elapsed() > Duration::from_secs(5) {
-            self.status_msg.clear();
-        }
-        self.screen.draw_status_bar(
-            format!(
-                "{:20} - {} lines {}",
-                if self.filename.is_empty() {
-                    "[No Name]"
-                } else {
-                    &self.filename
-                },
-                self.rows.len(),
-                if self.dirty > 0 { "(modified)" } else { "" }
-            ),
+        let rows = if window.buffer == self.current {
+            &mut self.rows
+        } else {
+            &mut self.buffers[window.buffer].rows
+        };
+        // the buffer may have shrunk underneath this window
+        let rowoff = window.rowoff.min(rows.len());
+
+        self.screen.set_region(region);
+        let height = self.screen.height() as usize;
+        self.screen.draw_rows(
+            &rows.visible(rowoff, height),
+            rowoff,
+            window.coloff,
+            window.cursor.y,
+            None,
+        )?;
+        self.screen.draw_status_bar(left, right, false)
+    }
+
+    fn status_text(
+        &self,
+        buffer: usize,
+        name: &str,
+        rows: &Text,
+        flags: &str,
+        syntax: Option<usize>,
+        cursor_y: usize,
+    ) -> (String, String) {
+        let lines = rows.len();
+        let buffer_no = if self.buffers.len() > 1 {
+            format!("[{}/{}] ", buffer + 1, self.buffers.len())
+        } else {
+            String::new()
+        };
+        (
+            format!("{buffer_no}{:20} - {} lines {}", name, lines, flags),
             format!(
-                "{} | {}/{}",
-                if let Some(ft) = self.syntax {
+                "{} | {} | {}/{}",
+                if let Some(ft) = syntax {
                     self.hldb[ft].filetype.as_str()
                 } else {
                     "no ft"
                 },
-                self.cursor.y + 1,
-                self.rows.len()
+                rows.format(),
+                cursor_y + 1,
+                lines
             ),
-            &self.status_msg,
         )
     }
 
+    /* "[RO]" for a buffer in view mode, "(modified)" for one with unsaved changes */
+    fn buffer_flags(dirty: usize, read_only: bool) -> String {
+        let mut flags = Vec::new();
+        if read_only {
+            flags.push("[RO]");
+        }
+        if dirty > 0 {
+            flags.push("(modified)");
+        }
+        flags.join(" ")
+    }
+
     pub fn die<S1: Display, S2: Display>(&mut self, message: S1, err: S2) {
-        let _ = self.screen.clear(&self.rows, 0);
+        let _ = self.screen.clear();
         let _ = terminal::disable_raw_mode();
         eprintln!("{}: {}", message, err);
         std::process::exit(1);
@@ -241,43 +951,67 @@ impl Editor {
     fn move_cursor(&mut self, key: EditorKey) {
         use EditorKey::*;
 
+        self.history.seal();
+
         match key {
             Left => {
                 if self.cursor.x != 0 {
-                    self.cursor.x -= 1;
+                    self.cursor.x = self.rows.row(self.cursor.y).prev_grapheme(self.cursor.x);
                 } else if self.cursor.y > 0 {
                     self.cursor.y -= 1;
-                    self.cursor.x = self.rows[self.cursor.row()].len() as u16;
+                    self.cursor.x = self.rows.line_len(self.cursor.row());
                 }
             }
-            Right => {
-                if (self.cursor.y as usize) < self.rows.len() {
-                    let idx = self.cursor.row();
-                    if self.cursor.left_of(self.rows[idx].len()) {
-                        self.cursor.x += 1;
-                    } else if self.cursor.above(self.rows.len()) {
-                        self.cursor.y += 1;
-                        self.cursor.x = 0;
-                    }
+            Right if self.cursor.y < self.rows.len() => {
+                let idx = self.cursor.row();
+                if self.cursor.left_of(self.rows.line_len(idx)) {
+                    self.cursor.x = self.rows.row(idx).next_grapheme(self.cursor.x);
+                } else if self.cursor.above(self.rows.len()) {
+                    self.cursor.y += 1;
+                    self.cursor.x = 0;
                 }
             }
-            Up => {
-                self.cursor.y = self.cursor.y.saturating_sub(1);
+            Up | Down => {
+                // stay in the same screen column, whatever the characters in between
+                let rx = self.cursor_rx();
+                if matches!(key, Up) {
+                    self.cursor.y = self.cursor.y.saturating_sub(1);
+                } else if self.cursor.y < self.rows.len() {
+                    self.cursor.y += 1;
+                }
+                self.cursor.x = if self.cursor.above(self.rows.len()) {
+                    self.rows.row(self.cursor.y).rx_to_cx(rx)
+                } else {
+                    0
+                };
             }
-            Down if self.cursor.y < self.rows.len() as u16 => self.cursor.y += 1,
             _ => {}
         }
 
-        let rowlen = self.current_row_len();
-        self.cursor.x = self.cursor.x.min(rowlen);
+        self.snap_cursor();
     }
 
-    fn scroll(&mut self) {
-        self.render_x = if self.cursor.above(self.rows.len()) {
-            self.rows[self.cursor.y as usize].cx_to_rx(self.cursor.x)
+    /* the screen column the cursor is on */
+    fn cursor_rx(&mut self) -> usize {
+        if self.cursor.above(self.rows.len()) {
+            self.rows.row(self.cursor.y).cx_to_rx(self.cursor.x)
+        } else {
+            0
+        }
+    }
+
+    /* keeps the cursor within its row, on a grapheme boundary */
+    fn snap_cursor(&mut self) {
+        self.cursor.x = if self.cursor.above(self.rows.len()) {
+            self.rows.row(self.cursor.y).snap(self.cursor.x)
         } else {
             0
         };
+    }
+
+    fn scroll(&mut self) {
+        self.snap_cursor();
+        self.render_x = self.cursor_rx();
 
         let bounds = self.screen.bounds();
 
@@ -297,9 +1031,9 @@ impl Editor {
         }
     }
 
-    fn current_row_len(&self) -> u16 {
+    fn current_row_len(&self) -> usize {
         if self.cursor.above(self.rows.len()) {
-            self.rows[self.cursor.y as usize].len() as u16
+            self.rows.line_len(self.cursor.y)
         } else {
             0
         }
@@ -307,11 +1041,13 @@ impl Editor {
 
     fn insert_char(&mut self, c: char) {
         if !self.cursor.above(self.rows.len()) {
+            self.history.begin_group();
             self.insert_row(self.rows.len(), String::new());
+            self.insert_text(self.cursor, &c.to_string());
+            self.history.end_group();
+        } else {
+            self.insert_text(self.cursor, &c.to_string());
         }
-        self.rows[self.cursor.y as usize].insert_char(self.cursor.x as usize, c);
-        self.cursor.x += 1;
-        self.dirty += 1;
     }
 
     fn del_char(&mut self) {
@@ -322,47 +1058,47 @@ impl Editor {
             return;
         }
 
-        let cur_row = self.cursor.y as usize;
+        let cur_row = self.cursor.y;
 
         if self.cursor.x > 0 {
-            if self.rows[cur_row].del_char(
-                self.cursor.x as usize - 1,
+            let row = self.rows.row(cur_row);
+            let start = row.del_char_start(
+                row.prev_grapheme(self.cursor.x),
                 self.options.auto_indent == Indentation::On,
-            ) {
-                self.dirty += 1;
-                if self.cursor.x >= self.rows[cur_row].len() as u16 {
-                    self.cursor.x = self.rows[cur_row].len() as u16;
-                } else {
-                    self.cursor.x -= 1;
-                }
-            }
+            );
+            let start = Position {
+                x: start,
+                y: self.cursor.y,
+            };
+            self.delete_text(start, self.cursor);
         } else {
-            self.cursor.x = self.rows[cur_row - 1].len() as u16;
-            if let Some(row) = self.del_row(cur_row) {
-                self.rows[cur_row - 1].append_string(&row);
-                self.cursor.y -= 1;
-                self.dirty += 1;
-            }
+            let end_of_prev = Position {
+                x: self.rows.line_len(cur_row - 1),
+                y: self.cursor.y - 1,
+            };
+            self.delete_text(end_of_prev, self.cursor);
         }
     }
 
     fn insert_newline(&mut self) {
-        let row = self.cursor.y as usize;
+        let row = self.cursor.y;
 
-        self.cursor.x = if self.cursor.x == 0 {
-            self.insert_row(row, String::from(""));
-            0
+        if self.cursor.x == 0 || row >= self.rows.len() {
+            if row >= self.rows.len() {
+                self.insert_row(row, String::from(""));
+            } else {
+                self.insert_text(self.cursor, "\n");
+            }
+            self.cursor.x = 0;
+            self.cursor.y = row + 1;
         } else {
-            let new_row = self.rows[row].split(self.cursor.x as usize);
             let indent_level = if self.options.auto_indent == Indentation::On {
-                self.rows[row].indent_level()
+                self.rows.row(row).indent_level().min(self.cursor.x)
             } else {
                 0
             };
-            self.insert_row(row + 1, format!("{:0indent_level$}{new_row}", ""));
-            indent_level as u16
-        };
-        self.cursor.y += 1;
+            self.insert_text(self.cursor, &format!("\n{:indent_level$}", ""));
+        }
     }
 
     fn insert_row(&mut self, at: usize, s: String) {
@@ -370,78 +1106,620 @@ impl Editor {
             return;
         }
 
-        self.rows.insert(at, Row::new(s));
-        self.dirty += 1;
+        self.record_edit(Edit::InsertRow { at, text: s });
     }
 
-    fn del_row(&mut self, at: usize) -> Option<String> {
-        if at >= self.rows.len() {
-            None
-        } else {
-            self.dirty += 1;
-            Some(self.rows.remove(at).chars)
+    /* inserts `text` (which may contain newlines) and leaves the cursor after it */
+    fn insert_text(&mut self, at: Position, text: &str) {
+        if text.is_empty() {
+            return;
         }
+        self.cursor = at;
+        self.record_edit(Edit::Insert {
+            at,
+            text: text.to_string(),
+        });
     }
 
-    fn rows_to_string(&self) -> String {
-        let mut buf = String::new();
-        for r in &self.rows {
-            buf.push_str(r.chars.as_str());
-            buf.push('\n');
+    /* deletes everything from `start` up to (but not including) `end` */
+    fn delete_text(&mut self, start: Position, end: Position) -> String {
+        let text = self.text_range(start, end);
+        if !text.is_empty() {
+            self.cursor = end;
+            self.record_edit(Edit::Delete {
+                at: start,
+                text: text.clone(),
+            });
         }
+        text
+    }
 
-        buf
+    fn text_range(&self, start: Position, end: Position) -> String {
+        self.rows.text_range(start, end)
     }
 
-    fn save(&mut self) {
-        if self.filename.is_empty() {
-            if let Some(filename) = self.prompt("Save as", None) {
-                self.filename = filename;
-            } else {
-                self.set_status_message("Save aborted");
-                return;
-            }
-            self.select_syntax_highlight()
+    fn record_edit(&mut self, edit: Edit) {
+        let before = self.cursor;
+        self.cursor = self.apply_edit(&edit);
+        self.history.record(edit, before, self.cursor);
+        self.dirty += 1;
+    }
+
+    /* applies an edit to the rows, returning where the cursor ends up */
+    fn apply_edit(&mut self, edit: &Edit) -> Position {
+        self.mark = None;
+        match edit {
+            Edit::Insert { at, text } => {
+                self.rows.insert(*at, text);
+                match text.rfind('\n') {
+                    Some(idx) => Position {
+                        x: text.len() - idx - 1,
+                        y: at.y + text.matches('\n').count(),
+                    },
+                    None => Position {
+                        x: at.x + text.len(),
+                        y: at.y,
+                    },
+                }
+            }
+            Edit::Delete { at, text } => {
+                self.rows.remove(*at, text.len());
+                *at
+            }
+            Edit::InsertRow { at, text } => {
+                self.rows.insert_row(*at, text);
+                Position { x: 0, y: *at }
+            }
+            Edit::DeleteRow { at, .. } => {
+                self.rows.remove_row(*at);
+                Position { x: 0, y: *at }
+            }
+        }
+    }
+
+    /* the selected region, in text order, if anything is selected */
+    fn selection(&self) -> Option<(Position, Position)> {
+        match self.mark {
+            Some(mark) if mark < self.cursor => Some((mark, self.cursor)),
+            Some(mark) if mark > self.cursor => Some((self.cursor, mark)),
+            _ => None,
+        }
+    }
+
+    /* Shift extends the selection from wherever the cursor is, anything else drops it */
+    fn update_mark(&mut self, extend: bool) {
+        if !extend {
+            self.mark = None;
+        } else if self.mark.is_none() {
+            self.mark = Some(self.cursor);
+        }
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        if let Some((start, end)) = self.selection() {
+            self.delete_text(start, end);
+            true
+        } else {
+            false
+        }
+    }
+
+    /* runs an edit so that it replaces the selection (if any) in a single undo step */
+    fn replace_selection(&mut self, edit: impl FnOnce(&mut Editor)) {
+        if self.selection().is_some() {
+            self.history.begin_group();
+            self.delete_selection();
+            edit(self);
+            self.history.end_group();
+        } else {
+            edit(self);
         }
+    }
 
-        let buf = self.rows_to_string();
-        let len = buf.as_bytes().len();
-        if let Err(e) = std::fs::write(&self.filename, &buf) {
-            self.set_status_message(&format!("Can't save! I/O error: {e}"))
+    fn copy(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.clipboard = self.text_range(start, end);
+            self.mark = None;
+            self.set_status_message(format!("Copied {} bytes", self.clipboard.len()));
         } else {
+            self.set_status_message("Nothing selected");
+        }
+    }
+
+    fn cut(&mut self) {
+        if self.refuse_if_read_only() {
+            return;
+        }
+        if let Some((start, end)) = self.selection() {
+            self.clipboard = self.delete_text(start, end);
+            self.set_status_message(format!("Cut {} bytes", self.clipboard.len()));
+        } else {
+            self.set_status_message("Nothing selected");
+        }
+    }
+
+    fn paste(&mut self) {
+        if self.refuse_if_read_only() {
+            return;
+        }
+        if self.clipboard.is_empty() {
+            self.set_status_message("Clipboard is empty");
+            return;
+        }
+
+        let text = self.clipboard.clone();
+        self.history.begin_group();
+        self.delete_selection();
+        if !self.cursor.above(self.rows.len()) {
+            self.insert_row(self.rows.len(), String::new());
+        }
+        self.insert_text(self.cursor, &text);
+        self.history.end_group();
+    }
+
+    fn undo(&mut self) {
+        if self.refuse_if_read_only() {
+            return;
+        }
+        if let Some(step) = self.history.undo() {
+            for edit in step.edits.iter().rev() {
+                self.apply_edit(&edit.inverse());
+            }
+            self.cursor = step.cursor_before;
+            self.update_dirty_from_history();
+        } else {
+            self.set_status_message("Nothing to undo");
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.refuse_if_read_only() {
+            return;
+        }
+        if let Some(step) = self.history.redo() {
+            for edit in step.edits.iter() {
+                self.apply_edit(edit);
+            }
+            self.cursor = step.cursor_after;
+            self.update_dirty_from_history();
+        } else {
+            self.set_status_message("Nothing to redo");
+        }
+    }
+
+    fn update_dirty_from_history(&mut self) {
+        if self.history.is_saved() {
             self.dirty = 0;
-            self.set_status_message(&format!("{len} bytes written to disk"));
+        } else {
+            self.dirty += 1;
+        }
+    }
+
+    fn toggle_read_only(&mut self) {
+        self.read_only = !self.read_only;
+        self.set_status_message(if self.read_only {
+            "View mode: the buffer can't be changed"
+        } else {
+            "Editing enabled"
+        });
+    }
+
+    /* true (and says why) when the active buffer is in view mode */
+    fn refuse_if_read_only(&mut self) -> bool {
+        if self.read_only {
+            self.set_status_message("Buffer is read-only (Alt-R to allow editing)");
+        }
+        self.read_only
+    }
+
+    /* asks which line endings the buffer should be saved with */
+    fn convert_line_endings(&mut self) {
+        if self.refuse_if_read_only() {
+            return;
+        }
+        let line_ending = match self.read_choice("Line endings: (l)f, (c)rlf or c(r)?", "lcr") {
+            Some('l') => LineEnding::Lf,
+            Some('c') => LineEnding::CrLf,
+            Some('r') => LineEnding::Cr,
+            _ => {
+                self.set_status_message("Conversion aborted");
+                return;
+            }
+        };
+        self.set_line_ending(line_ending);
+    }
+
+    /* changing the format isn't undoable, so no earlier state matches the file any more */
+    fn set_line_ending(&mut self, line_ending: LineEnding) {
+        let format = self.rows.format();
+        if format.line_ending != line_ending {
+            self.rows.set_format(FileFormat {
+                line_ending,
+                ..format
+            });
+            self.history.forget_saved();
+            self.dirty += 1;
+        }
+        self.set_status_message(format!("Line endings set to {line_ending}"));
+    }
+
+    fn save(&mut self) {
+        if self.refuse_if_read_only() {
+            return;
+        }
+        if self.filename.is_empty() {
+            if let Some(filename) = self.prompt(PromptKind::SaveAs, "Save as", None) {
+                self.filename = filename;
+            } else {
+                self.set_status_message("Save aborted");
+                return;
+            }
+            self.select_syntax_highlight()
+        }
+
+        let path = PathBuf::from(&self.filename);
+        if self
+            .disk
+            .as_mut()
+            .and_then(|disk| disk.changed(&path))
+            .is_some()
+        {
+            let question = format!(
+                "{} changed on disk since it was read. Overwrite it? (y/n)",
+                self.filename
+            );
+            if self.read_choice(&question, "yn") != Some('y') {
+                self.set_status_message("Save aborted");
+                return;
+            }
+        }
+
+        if let Some(len) = self.write_file() {
+            self.set_status_message(format!("{len} bytes written to disk"));
+        }
+    }
+
+    /* writes the active buffer to its file, returning how many bytes were written */
+    fn write_file(&mut self) -> Option<usize> {
+        let buf = match self.rows.to_file() {
+            Ok(buf) => buf,
+            Err(e) => {
+                self.set_status_message(format!("Can't save! {e}"));
+                return None;
+            }
+        };
+        let path = PathBuf::from(&self.filename);
+        let backup = self.options.backup == Backup::On;
+        if let Err(e) = save_file(&path, &buf, backup) {
+            self.set_status_message(format!("Can't save! I/O error: {e}"));
+            return None;
+        }
+
+        self.disk = Some(DiskState::new(&path, &buf));
+        self.declined_disk = None;
+        self.dirty = 0;
+        self.history.mark_saved();
+        self.remove_swap();
+        Some(buf.len())
+    }
+
+    /* saves every modified buffer that has a name, without asking anything */
+    fn autosave(&mut self) {
+        let active = self.current;
+        let (mut tried, mut saved) = (0, 0);
+        for idx in 0..self.buffers.len() {
+            let buffer = &self.buffers[idx];
+            let (filename, dirty, read_only) = if idx == self.current {
+                (&self.filename, self.dirty, self.read_only)
+            } else {
+                (&buffer.filename, buffer.dirty, buffer.read_only)
+            };
+            if dirty == 0 || filename.is_empty() || read_only {
+                continue;
+            }
+
+            tried += 1;
+            self.activate_buffer(idx);
+            let path = PathBuf::from(&self.filename);
+            if self
+                .disk
+                .as_mut()
+                .and_then(|disk| disk.changed(&path))
+                .is_some()
+            {
+                self.set_status_message(format!(
+                    "{} changed on disk, not autosaved",
+                    self.filename
+                ));
+            } else if self.write_file().is_some() {
+                saved += 1;
+            }
+        }
+        self.activate_buffer(active);
+
+        // leave any error in the status bar
+        if saved > 0 && saved == tried {
+            self.set_status_message(format!("Autosaved {saved} buffer(s)"));
+        }
+    }
+
+    /* keeps swap files in `dir`, so unsaved changes survive a crash */
+    pub fn set_swap_dir(&mut self, dir: PathBuf) {
+        self.swap_dir = Some(dir);
+    }
+
+    /* where the swap file for `filename` goes; unnamed buffers don't get one */
+    fn swap_path_for(&self, filename: &str) -> Option<PathBuf> {
+        if filename.is_empty() {
+            return None;
+        }
+        Some(swap_path(self.swap_dir.as_ref()?, filename))
+    }
+
+    /* writes the active buffer's changes to its swap file once enough of them build up */
+    fn update_swap(&mut self, force: bool) {
+        if let Some(e) = self.swaps.take_error() {
+            self.set_status_message(format!("Can't write swap file! {e}"));
+        }
+        if self.dirty == 0 {
+            if self.swap_dirty != 0 {
+                self.remove_swap();
+            }
+            return;
+        }
+
+        let scale = self.swap_scale();
+        let due = self.swap_time.elapsed() >= self.swap_interval()
+            || self.dirty >= self.swap_dirty + SWAP_EDITS * scale;
+        if self.dirty == self.swap_dirty || !(force || due) {
+            return;
+        }
+        let Some(path) = self.swap_path_for(&self.filename) else {
+            return;
+        };
+        // a swap file the user chose to keep isn't ours to overwrite
+        if self.swap_dirty == 0 && self.recoverable_swap(&self.filename).is_some() {
+            return;
+        }
+
+        self.swaps.write(path, &self.filename, self.rows.snapshot());
+        self.swap_dirty = self.dirty;
+        self.swap_time = Instant::now();
+    }
+
+    /* big buffers take longer to write out, so they're written less often */
+    fn swap_scale(&self) -> usize {
+        (1 + self.rows.len_bytes() / SWAP_SCALE_BYTES).min(MAX_SWAP_SCALE)
+    }
+
+    fn swap_interval(&self) -> Duration {
+        SWAP_INTERVAL * self.swap_scale() as u32
+    }
+
+    /* removes the active buffer's swap file, if this editor wrote one */
+    fn remove_swap(&mut self) {
+        if self.swap_dirty == 0 {
+            return;
+        }
+        if let Some(path) = self.swap_path_for(&self.filename) {
+            self.swaps.remove(path);
+        }
+        self.swap_dirty = 0;
+    }
+
+    /* called on a clean exit */
+    fn remove_all_swaps(&mut self) {
+        self.remove_swap();
+        for idx in 0..self.buffers.len() {
+            if self.buffers[idx].swap_dirty == 0 {
+                continue;
+            }
+            if let Some(path) = self.swap_path_for(&self.buffers[idx].filename) {
+                self.swaps.remove(path);
+            }
+        }
+    }
+
+    /* a swap file left behind for `filename` by an editor that didn't exit cleanly */
+    fn recoverable_swap(&self, filename: &str) -> Option<(PathBuf, Swap)> {
+        let path = self.swap_path_for(filename)?;
+        if !is_newer(&path, filename) {
+            return None;
+        }
+        match Swap::read(&path) {
+            Ok(swap) if swap.pid != std::process::id() => Some((path, swap)),
+            _ => None,
+        }
+    }
+
+    fn check_all_swaps(&mut self) {
+        let first = self.current;
+        for idx in 0..self.buffers.len() {
+            let filename = if idx == self.current {
+                &self.filename
+            } else {
+                &self.buffers[idx].filename
+            };
+            if self.recoverable_swap(filename).is_some() {
+                self.switch_buffer(idx);
+                self.check_swap();
+            }
+        }
+        self.switch_buffer(first);
+    }
+
+    /* offers to recover the active buffer from its swap file, if there is one */
+    fn check_swap(&mut self) {
+        let Some((path, swap)) = self.recoverable_swap(&self.filename) else {
+            return;
+        };
+
+        // a read-only buffer can't take the changes, so they can only be looked at
+        if self.read_only {
+            let question = format!(
+                "{} has unsaved changes from process {}: (d)iff or discard (x)?",
+                self.filename, swap.pid
+            );
+            match self.read_choice(&question, "dx") {
+                Some('d') => {
+                    self.show_diff(&swap.text);
+                    self.set_status_message("Reopen without --readonly to recover these changes");
+                }
+                choice => self.answer_swap(&path, &swap, choice),
+            }
+            return;
+        }
+
+        let file_buffer = self.current;
+        let mut question = format!(
+            "{} has unsaved changes from process {}: (r)ecover, (d)iff or discard (x)?",
+            self.filename, swap.pid
+        );
+        let choice = loop {
+            match self.read_choice(&question, "rdx") {
+                Some('d') => {
+                    self.show_diff(&swap.text);
+                    question = String::from("Recover these changes? (r)ecover or discard (x)");
+                }
+                choice => break choice,
+            }
+        };
+        self.switch_buffer(file_buffer);
+        self.answer_swap(&path, &swap, choice);
+    }
+
+    /* acts on the answer to check_swap's question; anything but (x) keeps the swap file */
+    fn answer_swap(&mut self, path: &Path, swap: &Swap, choice: Option<char>) {
+        match choice {
+            Some('r') => {
+                self.recover_swap(&swap.text);
+                self.set_status_message(format!("Recovered {}; save to keep it", self.filename));
+            }
+            Some('x') => {
+                let _ = std::fs::remove_file(path);
+                self.set_status_message(format!("Discarded unsaved changes to {}", self.filename));
+            }
+            _ => self.set_status_message(format!("Kept the swap file for {}", self.filename)),
+        }
+    }
+
+    /* opens a new buffer showing how `text` differs from the active buffer */
+    fn show_diff(&mut self, text: &str) {
+        let diff = diff_lines(&self.rows.to_string(), text);
+        let first_change = diff
+            .lines()
+            .position(|line| !line.starts_with(' '))
+            .unwrap_or_default();
+
+        let mut buffer = self.new_buffer(&diff, String::new());
+        buffer.read_only = true;
+        buffer.cursor.y = first_change;
+        buffer.rowoff = first_change.saturating_sub(2);
+        self.add_buffer(buffer);
+    }
+
+    /* replaces the active buffer's text, as an edit that can be undone */
+    fn recover_swap(&mut self, text: &str) {
+        if self.rows.is_empty() {
+            self.insert_row(0, String::new());
+        } else {
+            self.delete_text(
+                Position::default(),
+                Position {
+                    x: 0,
+                    y: self.rows.len(),
+                },
+            );
+        }
+        self.insert_text(Position::default(), text.strip_suffix('\n').unwrap_or(text));
+        self.cursor = Position::default();
+    }
+
+    /* the history for one kind of prompt */
+    fn history_for(&mut self, kind: PromptKind) -> &mut PromptHistory {
+        self.prompt_history.entry(kind).or_default()
+    }
+
+    /* loads every prompt kind's history from `dir`, saving new entries back there */
+    pub fn load_prompt_history(&mut self, dir: &Path) {
+        for kind in PromptKind::ALL {
+            match PromptHistory::load(dir.join(kind.name())) {
+                Ok(history) => {
+                    self.prompt_history.insert(kind, history);
+                }
+                Err(e) => {
+                    self.set_status_message(format!("Can't read {} history: {e}", kind.name()))
+                }
+            }
         }
     }
 
     fn prompt(
         &mut self,
+        kind: PromptKind,
         prompt: &str,
-        callback: Option<fn(&mut Editor, &str, PromptKey)>,
+        callback: Option<fn(&mut Editor, &mut String, PromptKey)>,
     ) -> Option<String> {
-        let mut buf = String::from("");
+        let mut line = LineEditor::default();
+        self.history_for(kind).reset();
 
         loop {
-            self.set_status_message(&format!("{}: {}", prompt, buf));
+            let before = format!("{}{}: ", prompt, self.prompt_hint);
+            let message = format!("{}{}{}", before, line.as_str(), self.prompt_info);
+
+            // scroll the line sideways if the cursor would fall off the screen
+            let column = before.width() + line.column();
+            let overflow = (column + 1).saturating_sub(self.screen.text_area().width as usize);
+            let mut shift = 0;
+            let shown = message
+                .graphemes(true)
+                .skip_while(|g| {
+                    let skip = shift < overflow;
+                    if skip {
+                        shift += grapheme_width(g);
+                    }
+                    skip
+                })
+                .collect::<String>();
+            self.set_status_message(shown);
             let _ = self.refresh_screen();
+            let _ = self.screen.move_to_message_bar((column - shift) as u16);
 
             let _ = self.screen.flush();
-            if let Ok(input_event) = self.keyboard.read() {
+            // while matches are still being counted, only stop to handle a key
+            let event = if self.match_count.is_some() {
+                self.keyboard.read_timeout(Duration::ZERO)
+            } else {
+                self.keyboard.read().map(Some)
+            };
+            if let Ok(None) = event {
+                self.count_more_matches();
+                continue;
+            }
+            if let Ok(Some(input_event)) = event {
                 let mut prompt_key: Option<PromptKey> = None;
                 let c = if let InputEvent::Key(c) = input_event {
                     c
                 } else {
                     continue;
                 };
+                let mut edited = false;
                 match c {
                     KeyEvent {
                         code: KeyCode::Enter,
                         ..
                     } => {
+                        let mut buf = line.as_str().to_string();
                         if let Some(callback) = callback {
-                            callback(self, &buf, PromptKey::Enter);
+                            callback(self, &mut buf, PromptKey::Enter);
                         }
-                        self.set_status_message("");
+                        if let Err(e) = self.history_for(kind).add(&buf) {
+                            self.set_status_message(format!("Can't save history: {e}"));
+                        } else {
+                            self.set_status_message("");
+                        }
+                        self.prompt_hint.clear();
+                        self.prompt_info.clear();
                         return Some(buf);
                     }
 
@@ -449,8 +1727,10 @@ impl Editor {
                         code: KeyCode::Esc, ..
                     } => {
                         if let Some(callback) = callback {
-                            callback(self, &buf, PromptKey::Escape);
+                            callback(self, &mut line.as_str().to_string(), PromptKey::Escape);
                         }
+                        self.prompt_hint.clear();
+                        self.prompt_info.clear();
                         self.set_status_message("");
                         return None;
                     }
@@ -458,27 +1738,103 @@ impl Editor {
                     KeyEvent {
                         code: KeyCode::Char('h'),
                         modifiers: KeyModifiers::CONTROL,
+                        ..
                     }
                     | KeyEvent {
                         code: KeyCode::Backspace,
+                        modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                        ..
+                    } => edited = line.backspace(),
+
+                    KeyEvent {
+                        code: KeyCode::Delete,
+                        ..
+                    } => edited = line.delete(),
+
+                    /*
+                     * Ctrl-W or Alt-Backspace deletes the previous word, Ctrl-U
+                     * everything before the cursor
+                     */
+                    KeyEvent {
+                        code: KeyCode::Char('w'),
+                        modifiers: KeyModifiers::CONTROL,
                         ..
                     }
                     | KeyEvent {
-                        code: KeyCode::Delete,
+                        code: KeyCode::Backspace,
+                        modifiers: KeyModifiers::ALT,
+                        ..
+                    } => edited = line.delete_word(),
+
+                    KeyEvent {
+                        code: KeyCode::Char('u'),
+                        modifiers: KeyModifiers::CONTROL,
+                        ..
+                    } => edited = line.delete_to_start(),
+
+                    /*
+                     * Ctrl-V pastes the first line of the clipboard
+                     */
+                    KeyEvent {
+                        code: KeyCode::Char('v'),
+                        modifiers: KeyModifiers::CONTROL,
                         ..
                     } => {
-                        buf.pop();
+                        let text = self.clipboard.lines().next().unwrap_or("").to_string();
+                        line.insert_str(&text);
+                        edited = !text.is_empty();
                     }
 
+                    KeyEvent {
+                        code: KeyCode::Left,
+                        ..
+                    } => line.left(),
+                    KeyEvent {
+                        code: KeyCode::Right,
+                        ..
+                    } => line.right(),
+                    KeyEvent {
+                        code: KeyCode::Home,
+                        ..
+                    }
+                    | KeyEvent {
+                        code: KeyCode::Char('a'),
+                        modifiers: KeyModifiers::CONTROL,
+                        ..
+                    } => line.home(),
+                    KeyEvent {
+                        code: KeyCode::End, ..
+                    }
+                    | KeyEvent {
+                        code: KeyCode::Char('e'),
+                        modifiers: KeyModifiers::CONTROL,
+                        ..
+                    } => line.end(),
+
+                    /*
+                     * Up / Down step through search matches in the search prompt
+                     * and through the history everywhere else; Ctrl-P / Ctrl-N
+                     * always recall history
+                     */
+                    KeyEvent {
+                        code: KeyCode::Up, ..
+                    } if kind == PromptKind::Search => prompt_key = Some(PromptKey::Prev),
+                    KeyEvent {
+                        code: KeyCode::Down,
+                        ..
+                    } if kind == PromptKind::Search => prompt_key = Some(PromptKey::Next),
+
                     KeyEvent {
                         code: KeyCode::Up, ..
                     }
                     | KeyEvent {
-                        code: KeyCode::Left,
+                        code: KeyCode::Char('p'),
+                        modifiers: KeyModifiers::CONTROL,
                         ..
                     } => {
-                        if let Some(callback) = callback {
-                            callback(self, &buf, PromptKey::Prev);
+                        if let Some(entry) = self.history_for(kind).older() {
+                            line.set(entry);
+                            edited = true;
                         }
                     }
                     KeyEvent {
@@ -486,28 +1842,47 @@ impl Editor {
                         ..
                     }
                     | KeyEvent {
-                        code: KeyCode::Right,
+                        code: KeyCode::Char('n'),
+                        modifiers: KeyModifiers::CONTROL,
                         ..
                     } => {
-                        if let Some(callback) = callback {
-                            callback(self, &buf, PromptKey::Next);
+                        if let Some(entry) = self.history_for(kind).newer() {
+                            line.set(entry);
+                            edited = true;
                         }
                     }
 
+                    KeyEvent {
+                        code: KeyCode::Tab, ..
+                    } => prompt_key = Some(PromptKey::Complete),
+
+                    KeyEvent {
+                        code: KeyCode::Char(ch),
+                        modifiers: KeyModifiers::ALT,
+                        ..
+                    } => prompt_key = Some(PromptKey::Toggle(ch)),
+
                     KeyEvent {
                         code: KeyCode::Char(ch),
                         modifiers: modif,
+                        ..
                     } => {
                         if matches!(modif, KeyModifiers::NONE | KeyModifiers::SHIFT) {
-                            prompt_key = Some(PromptKey::Char(ch));
-                            buf.push(ch);
+                            line.insert_str(ch.encode_utf8(&mut [0; 4]));
+                            edited = true;
                         }
                     }
                     _ => {}
                 }
-                if let Some(callback) = callback {
-                    if let Some(key) = prompt_key {
-                        callback(self, &buf, key);
+                if edited {
+                    prompt_key = Some(PromptKey::Char);
+                }
+                if let (Some(callback), Some(key)) = (callback, prompt_key) {
+                    // callbacks may rewrite the input, e.g. to complete it
+                    let mut buf = line.as_str().to_string();
+                    callback(self, &mut buf, key);
+                    if buf != line.as_str() {
+                        line.set(buf);
                     }
                 }
             }
@@ -515,13 +1890,11 @@ impl Editor {
     }
 
     fn find_callback(&mut self, query: &str, event: PromptKey) {
-        if let Some(saved_hl) = self.saved_hl {
-            self.rows[saved_hl].reset_match();
-            self.saved_hl = None;
-        }
-
         match event {
             PromptKey::Enter | PromptKey::Escape => {
+                self.search_matcher = None;
+                self.match_count = None;
+                self.highlight_search_matches();
                 self.last_match = None;
                 self.direction = SearchDirection::Forward;
                 return;
@@ -529,52 +1902,157 @@ impl Editor {
 
             PromptKey::Next => self.direction = SearchDirection::Forward,
             PromptKey::Prev => self.direction = SearchDirection::Backward,
+            PromptKey::Toggle(key) => {
+                if !self.search_mode.toggle(key) {
+                    return;
+                }
+                self.last_match = None;
+                self.direction = SearchDirection::Forward;
+            }
             _ => {
                 self.last_match = None;
                 self.direction = SearchDirection::Forward;
             }
         }
 
-        let mut current = if let Some(line) = self.last_match {
-            line
-        } else {
+        self.search_matcher = None;
+        self.prompt_info.clear();
+        let matcher = match Matcher::new(query, self.search_mode) {
+            Ok(matcher) => matcher,
+            Err(_) => {
+                self.prompt_hint = format!("{} (invalid regex)", self.search_mode);
+                return;
+            }
+        };
+        self.prompt_hint = self.search_mode.to_string();
+
+        if self.last_match.is_none() {
             self.direction = SearchDirection::Forward;
-            self.rows.len()
+        }
+
+        if let Some(found) = self.next_match(&matcher, self.last_match) {
+            self.last_match = Some(found);
+            self.cursor = found;
+            self.rowoff = self.rows.len();
+        }
+
+        self.search_matcher = Some(matcher);
+        self.match_count = None;
+        if !query.is_empty() {
+            self.match_count = Some(MatchCount::default());
+            self.count_more_matches();
+        }
+    }
+
+    /* the first match after (or before, searching backward) `from`, wrapping around */
+    fn next_match(&self, matcher: &Matcher, from: Option<Position>) -> Option<Position> {
+        let count = self.rows.len();
+        if count == 0 {
+            return None;
+        }
+        let (start_row, from_x) = match from {
+            Some(pos) => (pos.row(), Some(pos.x)),
+            None => (0, None),
         };
 
-        for _ in 0..self.rows.len() {
-            match self.direction {
+        for i in 0..=count {
+            let (y, found) = match self.direction {
                 SearchDirection::Forward => {
-                    current += 1;
-                    if current >= self.rows.len() {
-                        current = 0;
-                    }
+                    let y = (start_row + i) % count;
+                    let found = matcher.find_all(&self.rows.line(y));
+                    let found = match from_x {
+                        Some(x) if i == 0 => found.into_iter().find(|(start, _)| *start > x),
+                        _ => found.first().copied(),
+                    };
+                    (y, found)
                 }
                 SearchDirection::Backward => {
-                    if current == 0 {
-                        current = self.rows.len() - 1;
-                    } else {
-                        current -= 1;
-                    }
+                    let y = (start_row + count - i % count) % count;
+                    let found = matcher.find_all(&self.rows.line(y));
+                    let found = match from_x {
+                        Some(x) if i == 0 => found.into_iter().rev().find(|(start, _)| *start < x),
+                        _ => found.last().copied(),
+                    };
+                    (y, found)
                 }
+            };
+
+            if let Some((start, _)) = found {
+                return Some(Position { x: start, y });
             }
+        }
+        None
+    }
 
-            if let Some(m) = self.rows[current]
-                .render
-                .match_indices(query)
-                .take(1)
-                .next()
-            {
-                let start = m.0;
+    /* counts the search matches in the next chunk of lines, so a big file doesn't hold up typing */
+    fn count_more_matches(&mut self) {
+        let (Some(matcher), Some(count)) = (&self.search_matcher, self.match_count.as_mut()) else {
+            self.match_count = None;
+            return;
+        };
+        let end = (count.line + MATCH_COUNT_LINES).min(self.rows.len());
+        while count.line < end && !count.is_capped() {
+            let y = count.line;
+            let current = self.last_match.filter(|pos| pos.row() == y);
+            count.add(
+                &matcher.find_all(&self.rows.line(y)),
+                current.map(|pos| pos.x),
+            );
+        }
+        count.done = count.line == self.rows.len() || count.is_capped();
+
+        self.prompt_info = count.info();
+        if count.done {
+            self.match_count = None;
+        }
+    }
+
+    /* marks every search match on the screen, clearing marks from the last refresh */
+    fn highlight_search_matches(&mut self) {
+        for idx in self.highlighted_rows.drain(..) {
+            if let Some(row) = self.rows.cached_mut(idx) {
+                row.reset_match();
+            }
+        }
+
+        let matcher = if let Some(matcher) = &self.search_matcher {
+            matcher
+        } else {
+            return;
+        };
 
-                self.last_match = Some(current);
-                self.cursor.y = current as u16;
-                self.cursor.x = self.rows[current].rx_to_cx(start);
-                self.rowoff = self.rows.len() as u16;
+        let first = self.rowoff;
+        let last = (first + self.screen.height() as usize).min(self.rows.len());
+        for idx in first..last {
+            let row = self.rows.row_mut(idx);
+            let to_render = |(start, end): (usize, usize)| (row.cx_to_rx(start), row.cx_to_rx(end));
+            let found = matcher
+                .find_all(&row.chars)
+                .into_iter()
+                .map(to_render)
+                .collect::<Vec<(usize, usize)>>();
+            if found.is_empty() {
+                continue;
+            }
+
+            let current = self
+                .last_match
+                .filter(|pos| pos.row() == idx)
+                .map(|pos| row.cx_to_rx(pos.x))
+                .and_then(|rx| found.iter().find(|m| m.0 == rx))
+                .copied();
+            row.highlight_other_matches(found);
+            if let Some((start, end)) = current {
+                row.highlight_match(start, end - start);
+            }
+            self.highlighted_rows.push(idx);
+        }
+    }
 
-                self.rows[current].highlight_match(start, query.len());
-                self.saved_hl = Some(current);
-                break;
+    fn search_mode_callback(&mut self, _query: &mut String, event: PromptKey) {
+        if let PromptKey::Toggle(key) = event {
+            if self.search_mode.toggle(key) {
+                self.prompt_hint = self.search_mode.to_string();
             }
         }
     }
@@ -582,8 +2060,13 @@ impl Editor {
     fn find(&mut self) {
         let (saved_position, saved_coloff, saved_rowoff) = (self.cursor, self.coloff, self.rowoff);
 
+        self.prompt_hint = self.search_mode.to_string();
         if self
-            .prompt("Search (Use ESC/Arrows/Enter)", Some(Editor::find_callback))
+            .prompt(
+                PromptKind::Search,
+                "Search (Use ESC/Up/Down/Enter, Alt-R/C/W)",
+                Some(|ed, query, key| ed.find_callback(query, key)),
+            )
             .is_none()
         {
             self.cursor = saved_position;
@@ -592,8 +2075,134 @@ impl Editor {
         }
     }
 
+    fn replace(&mut self) {
+        if self.refuse_if_read_only() {
+            return;
+        }
+        self.prompt_hint = self.search_mode.to_string();
+        let query = match self.prompt(
+            PromptKind::Search,
+            "Replace (Alt-R/C/W)",
+            Some(Editor::search_mode_callback),
+        ) {
+            Some(query) if !query.is_empty() => query,
+            _ => {
+                self.set_status_message("Replace aborted");
+                return;
+            }
+        };
+        let matcher = match Matcher::new(&query, self.search_mode) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                self.set_status_message(format!("Invalid regex: {e}"));
+                return;
+            }
+        };
+        let replacement = if let Some(replacement) = self.prompt(
+            PromptKind::Replace,
+            &format!("Replace '{query}' with"),
+            None,
+        ) {
+            replacement
+        } else {
+            self.set_status_message("Replace aborted");
+            return;
+        };
+
+        let count = self.replace_matches(&matcher, &replacement, Editor::ask_replace);
+        self.set_status_message(format!(
+            "Replaced {count} occurrence{}",
+            if count == 1 { "" } else { "s" }
+        ));
+    }
+
+    /* replaces every match from the cursor onwards that `confirm` agrees to */
+    fn replace_matches(
+        &mut self,
+        matcher: &Matcher,
+        replacement: &str,
+        mut confirm: impl FnMut(&mut Editor) -> ReplaceChoice,
+    ) -> usize {
+        let mut count = 0;
+        let mut choice = ReplaceChoice::Yes;
+        let mut pos = self.cursor;
+
+        self.history.begin_group();
+        while pos.row() < self.rows.len() {
+            let line = self.rows.line(pos.row()).into_owned();
+            let (start, end) = if let Some(found) = matcher.find_at(&line, pos.x) {
+                found
+            } else {
+                pos = Position { x: 0, y: pos.y + 1 };
+                continue;
+            };
+
+            let text = matcher.replacement(&line, start, replacement);
+            pos.x = start;
+            self.cursor = pos;
+
+            if choice != ReplaceChoice::All {
+                let row = self.rows.row_mut(pos.row());
+                let render_start = row.cx_to_rx(start);
+                let render_len = row.cx_to_rx(end) - render_start;
+                row.highlight_match(render_start, render_len);
+                choice = confirm(self);
+                self.rows.row_mut(pos.row()).reset_match();
+            }
+
+            match choice {
+                ReplaceChoice::Quit => break,
+                ReplaceChoice::No => pos.x = end,
+                ReplaceChoice::Yes | ReplaceChoice::All => {
+                    self.delete_text(pos, Position { x: end, y: pos.y });
+                    self.insert_text(pos, &text);
+                    pos = self.cursor;
+                    count += 1;
+                }
+            }
+        }
+        self.history.end_group();
+
+        count
+    }
+
+    fn ask_replace(&mut self) -> ReplaceChoice {
+        match self.read_choice("Replace this occurrence? (y)es (n)o (a)ll (q)uit", "ynaq") {
+            Some('y') => ReplaceChoice::Yes,
+            Some('n') => ReplaceChoice::No,
+            Some('a') => ReplaceChoice::All,
+            _ => ReplaceChoice::Quit,
+        }
+    }
+
+    /* asks a question until one of `choices` is pressed; None if Escape was pressed */
+    fn read_choice(&mut self, question: &str, choices: &str) -> Option<char> {
+        loop {
+            self.set_status_message(question);
+            let _ = self.refresh_screen();
+            let _ = self
+                .screen
+                .move_to(&self.cursor, self.render_x, self.rowoff, self.coloff);
+            let _ = self.screen.flush();
+
+            if let Ok(InputEvent::Key(KeyEvent { code, .. })) = self.keyboard.read() {
+                match code {
+                    KeyCode::Char(c) if choices.contains(c.to_ascii_lowercase()) => {
+                        self.set_status_message("");
+                        return Some(c.to_ascii_lowercase());
+                    }
+                    KeyCode::Esc => {
+                        self.set_status_message("");
+                        return None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
     fn set_status_message<T: Into<String>>(&mut self, message: T) {
-        self.status_time = Instant::now();
+        self.timers.schedule(Timer::StatusExpiry, STATUS_TIMEOUT);
         self.status_msg = message.into();
     }
 
@@ -603,44 +2212,105 @@ impl Editor {
 
     fn select_syntax_highlight(&mut self) {
         let old_syntax = self.syntax;
-        self.syntax = Editor::find_highlight(&self.hldb, &self.filename);
+        self.syntax = Editor::find_highlight(&self.hldb, &self.filename, &self.rows);
         if self.syntax != old_syntax {
-            self.update_remaining_lines(0, false);
+            self.rows.set_syntax(self.get_syntax_data());
         }
     }
 
-    fn update_remaining_lines(&mut self, start: usize, in_comment: bool) {
-        let mut in_multiline_comment = in_comment;
-        let syntax_data = self.get_syntax_data();
-
-        for r in self.rows[start..].iter_mut() {
-            let changed = r.update_syntax(in_multiline_comment, &syntax_data);
-            if !changed {
-                break;
+    /* adds the syntax definitions in `paths`, reporting any that can't be used */
+    pub fn load_syntax_files(&mut self, paths: &[PathBuf]) {
+        let mut syntaxes = Vec::new();
+        let mut errors = Vec::new();
+        for path in paths {
+            match EditorSyntax::load(path) {
+                Ok(syntax) => syntaxes.push(syntax),
+                Err(e) => errors.push(format!("{}: {e}", path.display())),
             }
-            in_multiline_comment = r.open_comment;
+        }
+        merge_syntax(&mut self.hldb, syntaxes);
+
+        // the definitions have moved around, so every buffer looks its own up again
+        self.syntax = Editor::find_highlight(&self.hldb, &self.filename, &self.rows);
+        self.rows.set_syntax(self.get_syntax_data());
+        for buffer in self.buffers.iter_mut() {
+            buffer.syntax = Editor::find_highlight(&self.hldb, &buffer.filename, &buffer.rows);
+            buffer
+                .rows
+                .set_syntax(buffer.syntax.map(|idx| self.hldb[idx].clone()));
+        }
+
+        if !errors.is_empty() {
+            self.set_status_message(format!("Bad syntax file! {}", errors.join("; ")));
         }
     }
 
-    fn find_highlight(hldb: &[EditorSyntax], filename: &str) -> Option<usize> {
-        if filename.is_empty() {
-            return None;
+    /* uses the theme the config asks for, or the default one if it can't be used */
+    pub fn load_theme(&mut self, config: &Config) {
+        match Theme::from_config(config) {
+            Ok(theme) => self.screen.set_theme(theme),
+            Err(e) => {
+                self.screen.set_theme(Theme::default());
+                self.set_status_message(format!("Bad theme! {e}"));
+            }
         }
-        match filename.split('.').collect::<Vec<&str>>().last() {
-            None => None,
-            Some(extension) => {
-                for (j, entry) in hldb.iter().enumerate() {
-                    for ext in entry.filematch.iter() {
-                        if ext == extension {
-                            return Some(j);
-                        }
-                    }
-                }
-                None
+    }
+
+    fn filetype_callback(&mut self, input: &mut String, event: PromptKey) {
+        self.prompt_info.clear();
+        if let PromptKey::Complete = event {
+            let candidates = self
+                .hldb
+                .iter()
+                .map(|s| s.filetype.clone())
+                .filter(|ft| ft.to_lowercase().starts_with(&input.to_lowercase()))
+                .collect::<Vec<_>>();
+            match candidates.len() {
+                0 => self.prompt_info = String::from("  (no matches)"),
+                1 => *input = candidates[0].clone(),
+                _ => self.prompt_info = format!("  ({})", candidates.join(" ")),
             }
         }
     }
 
+    fn choose_filetype(&mut self) {
+        match self.prompt(
+            PromptKind::Filetype,
+            "Filetype (Tab to complete, \"none\" for plain text)",
+            Some(Editor::filetype_callback),
+        ) {
+            Some(name) if !name.trim().is_empty() => self.set_filetype(name.trim()),
+            _ => self.set_status_message("Filetype unchanged"),
+        }
+    }
+
+    /* highlights the buffer as `name` (a filetype, extension or interpreter) whatever it's called */
+    fn set_filetype(&mut self, name: &str) {
+        let syntax = if name.eq_ignore_ascii_case("none") {
+            None
+        } else if let Some(idx) = syntax_by_name(&self.hldb, name) {
+            Some(idx)
+        } else {
+            self.set_status_message(format!("Unknown filetype '{name}'"));
+            return;
+        };
+
+        self.syntax = syntax;
+        self.rows.set_syntax(self.get_syntax_data());
+        let ft = self
+            .get_syntax_data()
+            .map_or("none".to_string(), |s| s.filetype);
+        self.set_status_message(format!("Filetype set to {ft}"));
+    }
+
+    fn find_highlight(hldb: &[EditorSyntax], filename: &str, rows: &Text) -> Option<usize> {
+        let head = rows.lines().take(MODELINE_LINES).collect::<Vec<_>>();
+        let tail = (MODELINE_LINES.max(rows.len().saturating_sub(MODELINE_LINES))..rows.len())
+            .map(|y| rows.line(y))
+            .collect::<Vec<_>>();
+        find_syntax(hldb, filename, &head, &tail)
+    }
+
     fn process_keypress(&mut self, key: KeyEvent) -> KeypressResult {
         match key {
             /*
@@ -649,11 +2319,18 @@ impl Editor {
             KeyEvent {
                 code: KeyCode::Char('q'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
-                if self.dirty > 0 && self.quit_times > 0 {
+                let unsaved = self.unsaved_buffers();
+                if unsaved > 0 && self.quit_times > 0 {
                     self.set_status_message(format!(
-                        "WARNING!!! File has unsaved changes. \
+                        "WARNING!!! {} unsaved changes. \
                                 Press Ctrl-Q {} more times to quit.",
+                        if unsaved == 1 && self.buffers.len() == 1 {
+                            "File has".to_string()
+                        } else {
+                            format!("{unsaved} buffer(s) have")
+                        },
                         self.quit_times
                     ));
                     self.quit_times -= 1;
@@ -669,37 +2346,182 @@ impl Editor {
             KeyEvent {
                 code: KeyCode::Char('s'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.save();
             }
 
+            /*
+             * Ctrl-O to open a file
+             */
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.open(),
+
+            /*
+             * Ctrl-N / Ctrl-P for the next / previous buffer, Ctrl-B to list
+             * them and Ctrl-W to close the current one
+             */
+            KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.cycle_buffer(true),
+
+            KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.cycle_buffer(false),
+
+            KeyEvent {
+                code: KeyCode::Char('b'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.list_buffers(),
+
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.close_buffer(),
+
+            /*
+             * Alt-S / Alt-V split the window horizontally / vertically, Alt-X
+             * closes it, Alt-O or Alt-arrows move focus and Alt-+ / Alt--
+             * resize it
+             */
+            KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } if "svxo+=-_".contains(c) => match c {
+                's' => self.split_window(false),
+                'v' => self.split_window(true),
+                'x' => self.close_window(),
+                'o' => self.cycle_window(),
+                '+' | '=' => self.resize_window(RESIZE_STEP),
+                _ => self.resize_window(-RESIZE_STEP),
+            },
+
+            KeyEvent {
+                code: code @ (KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.focus_neighbour(match code {
+                KeyCode::Left => Direction::Left,
+                KeyCode::Right => Direction::Right,
+                KeyCode::Up => Direction::Up,
+                _ => Direction::Down,
+            }),
+
+            /*
+             * Alt-R to switch view (read-only) mode on or off
+             */
+            KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.toggle_read_only(),
+
+            /*
+             * Alt-T to set the filetype by hand
+             */
+            KeyEvent {
+                code: KeyCode::Char('t'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.choose_filetype(),
+
+            /*
+             * Alt-L to change the line endings
+             */
+            KeyEvent {
+                code: KeyCode::Char('l'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.convert_line_endings(),
+
             /*
              * Ctrl-F to find
              */
             KeyEvent {
                 code: KeyCode::Char('f'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.find();
             }
 
             /*
-             * Ignore Ctrl-L and Escape keys
+             * Ctrl-R to search and replace
              */
             KeyEvent {
-                code: KeyCode::Char('l'),
+                code: KeyCode::Char('r'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.replace();
             }
-            | KeyEvent {
-                code: KeyCode::Esc, ..
+
+            /*
+             * Ctrl-C to copy, Ctrl-X to cut, Ctrl-V to paste
+             */
+            KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.copy(),
+
+            KeyEvent {
+                code: KeyCode::Char('x'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.cut(),
+
+            KeyEvent {
+                code: KeyCode::Char('v'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.paste(),
+
+            /*
+             * Ctrl-Z to undo, Ctrl-Y to redo
+             */
+            KeyEvent {
+                code: KeyCode::Char('z'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.undo(),
+
+            KeyEvent {
+                code: KeyCode::Char('y'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.redo(),
+
+            /*
+             * Ignore Ctrl-L, Escape drops the selection
+             */
+            KeyEvent {
+                code: KeyCode::Char('l'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
             } => {}
 
+            KeyEvent {
+                code: KeyCode::Esc, ..
+            } => self.mark = None,
+
             /*
              * Ctrl-h or Backspace or Delete to delete characters
              */
             KeyEvent {
                 code: KeyCode::Char('h'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Backspace,
@@ -709,6 +2531,9 @@ impl Editor {
                 code: KeyCode::Delete,
                 ..
             } => {
+                if self.refuse_if_read_only() || self.delete_selection() {
+                    return KeypressResult::Continue;
+                }
                 if let KeyEvent {
                     code: KeyCode::Delete,
                     ..
@@ -725,49 +2550,39 @@ impl Editor {
             KeyEvent {
                 code: KeyCode::Char(key),
                 modifiers: KeyModifiers::NONE,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Char(key),
                 modifiers: KeyModifiers::SHIFT,
-            } => self.insert_char(key),
+                ..
+            } if !self.refuse_if_read_only() => self.replace_selection(|ed| ed.insert_char(key)),
 
             KeyEvent {
                 code: KeyCode::Tab,
                 modifiers: KeyModifiers::NONE,
-            } => self.insert_char('\t'),
+                ..
+            } if !self.refuse_if_read_only() => self.replace_selection(|ed| ed.insert_char('\t')),
 
             /*
              * Handle all other special keycodes
              */
-            KeyEvent { code, .. } => match code {
-                KeyCode::Enter => {
-                    self.insert_newline();
-                }
-                KeyCode::Home => self.cursor.x = 0,
-                KeyCode::End => self.cursor.x = self.current_row_len(),
-                KeyCode::Up => self.move_cursor(EditorKey::Up),
-                KeyCode::Down => self.move_cursor(EditorKey::Down),
-                KeyCode::Left => self.move_cursor(EditorKey::Left),
-                KeyCode::Right => self.move_cursor(EditorKey::Right),
-                KeyCode::PageUp | KeyCode::PageDown => {
-                    let bounds = self.screen.bounds();
-
-                    match code {
-                        KeyCode::PageUp => self.cursor.y = self.rowoff,
-                        KeyCode::PageDown => {
-                            self.cursor.y =
-                                (self.rowoff + bounds.y - 1).min(self.rows.len() as u16);
-                        }
-                        _ => panic!("rust compiler broke"),
-                    }
-
-                    for _ in 0..bounds.y {
-                        self.move_cursor(if code == KeyCode::PageUp {
-                            EditorKey::Up
-                        } else {
-                            EditorKey::Down
-                        })
-                    }
+            KeyEvent {
+                code, modifiers, ..
+            } => match code {
+                KeyCode::Enter if !self.refuse_if_read_only() => {
+                    self.replace_selection(|ed| ed.insert_newline());
+                }
+                KeyCode::Home
+                | KeyCode::End
+                | KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Left
+                | KeyCode::Right
+                | KeyCode::PageUp
+                | KeyCode::PageDown => {
+                    self.update_mark(modifiers.contains(KeyModifiers::SHIFT));
+                    self.move_cursor_by_key(code);
                 }
                 _ => {}
             },
@@ -775,15 +2590,616 @@ impl Editor {
 
         KeypressResult::Continue
     }
+
+    fn move_cursor_by_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Home => {
+                self.history.seal();
+                self.cursor.x = 0;
+            }
+            KeyCode::End => {
+                self.history.seal();
+                self.cursor.x = self.current_row_len();
+            }
+            KeyCode::Up => self.move_cursor(EditorKey::Up),
+            KeyCode::Down => self.move_cursor(EditorKey::Down),
+            KeyCode::Left => self.move_cursor(EditorKey::Left),
+            KeyCode::Right => self.move_cursor(EditorKey::Right),
+            KeyCode::PageUp | KeyCode::PageDown => {
+                let bounds = self.screen.bounds();
+
+                match code {
+                    KeyCode::PageUp => self.cursor.y = self.rowoff,
+                    KeyCode::PageDown => {
+                        self.cursor.y = (self.rowoff + bounds.y - 1).min(self.rows.len());
+                    }
+                    _ => panic!("rust compiler broke"),
+                }
+
+                for _ in 0..bounds.y {
+                    self.move_cursor(if code == KeyCode::PageUp {
+                        EditorKey::Up
+                    } else {
+                        EditorKey::Down
+                    })
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::row::*;
+    use crate::test_dir::TestDir;
 
     #[test]
     fn first_row_is_empty() {
         let ed = Editor::new(Options::default()).expect("failed to create editor");
         assert_eq!(ed.current_row_len(), 0);
     }
+
+    fn editor_with(text: &str) -> Editor {
+        Editor::build(text, "", Options::default()).expect("failed to create editor")
+    }
+
+    #[test]
+    fn missing_file_opens_empty_buffer() {
+        let ed = Editor::with_files(Options::default(), &["tests/no-such-file.rs"])
+            .expect("failed to create editor");
+        assert!(ed.rows.is_empty());
+        assert_eq!(ed.filename, "tests/no-such-file.rs");
+        assert_eq!(
+            ed.get_syntax_data().map(|s| s.filetype),
+            Some("Rust".into())
+        );
+        assert_eq!(ed.dirty, 0);
+    }
+
+    #[test]
+    fn latin1_file_is_saved_in_latin1() {
+        let dir = TestDir::new("latin1");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, b"caf\xe9\r\n").unwrap();
+        let filename = path.to_string_lossy().to_string();
+
+        let mut ed =
+            Editor::with_files(Options::default(), &[&filename]).expect("failed to create editor");
+        assert_eq!(ed.rows.line(0), "caf\u{e9}");
+        assert_eq!(ed.rows.format().to_string(), "windows-1252 CRLF");
+
+        ed.cursor.x = ed.current_row_len();
+        ed.insert_char('!');
+        ed.save();
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9!\r\n");
+
+        ed.insert_char('\u{3c8}');
+        ed.save();
+        assert!(ed.status_msg.starts_with("Can't save!"));
+        assert_eq!(ed.dirty, 1);
+    }
+
+    #[test]
+    fn swap_file_follows_unsaved_changes() {
+        let dir = TestDir::new("swapdir");
+        let filename = dir.join("file.txt").to_string_lossy().to_string();
+        std::fs::write(&filename, "old\n").unwrap();
+
+        let mut ed =
+            Editor::with_files(Options::default(), &[&filename]).expect("failed to create editor");
+        ed.set_swap_dir(dir.to_path_buf());
+        let swap = swap_path(&dir, &filename);
+        ed.insert_char('x');
+        ed.update_swap(true);
+        ed.swaps.sync();
+        assert_eq!(Swap::read(&swap).unwrap().text, "xold\n");
+        // our own swap file is never offered for recovery
+        assert!(ed.recoverable_swap(&filename).is_none());
+
+        ed.save();
+        ed.swaps.sync();
+        assert!(!swap.exists());
+
+        ed.recover_swap("new\ntext\n");
+        assert_eq!(ed.rows.to_string(), "new\ntext\n");
+        ed.undo();
+        ed.undo();
+        assert_eq!(ed.rows.to_string(), "xold\n");
+    }
+
+    #[test]
+    fn only_discarding_removes_a_swap_file() {
+        let dir = TestDir::new("swapkeep");
+        let filename = dir.join("new.txt").to_string_lossy().to_string();
+        let path = swap_path(&dir, &filename);
+        std::fs::write(&path, format!("kilo-ed swap 1\n{filename}\nlost\n")).unwrap();
+
+        let mut ed =
+            Editor::with_files(Options::default(), &[&filename]).expect("failed to create editor");
+        ed.set_swap_dir(dir.to_path_buf());
+        let (_, swap) = ed.recoverable_swap(&filename).unwrap();
+
+        // Escape keeps it, and so does editing or closing the buffer afterwards
+        ed.answer_swap(&path, &swap, None);
+        assert!(ed.status_msg.starts_with("Kept"));
+        ed.insert_char('x');
+        ed.update_swap(true);
+        ed.remove_all_swaps();
+        ed.swaps.sync();
+        assert_eq!(Swap::read(&path).unwrap(), swap);
+
+        ed.answer_swap(&path, &swap, Some('x'));
+        assert!(ed.status_msg.starts_with("Discarded"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn swap_diffs_are_read_only() {
+        let mut ed = editor_with("one\ntwo\n");
+        ed.show_diff("one\n2\n");
+        assert!(ed.read_only);
+        assert_eq!(ed.rows.to_string(), " one\n-two\n+2\n");
+        assert_eq!(ed.cursor.y, 1);
+    }
+
+    #[test]
+    fn big_buffers_are_swapped_less_often() {
+        let ed = editor_with("small\n");
+        assert_eq!(ed.swap_interval(), SWAP_INTERVAL);
+        let ed = editor_with(&"x".repeat(SWAP_SCALE_BYTES * 2));
+        assert_eq!(ed.swap_interval(), SWAP_INTERVAL * 3);
+        let ed = editor_with(&"x".repeat(SWAP_SCALE_BYTES * 100));
+        assert_eq!(ed.swap_scale(), MAX_SWAP_SCALE);
+    }
+
+    #[test]
+    fn reload_keeps_the_cursor_line() {
+        let dir = TestDir::new("reload");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let filename = path.to_string_lossy().to_string();
+
+        let mut ed =
+            Editor::with_files(Options::default(), &[&filename]).expect("failed to create editor");
+        ed.cursor = Position { x: 4, y: 2 };
+        assert!(ed.disk.unwrap().changed(&path).is_none());
+
+        std::fs::write(&path, "zero\none\nTWO\nthree\n").unwrap();
+        assert!(ed.disk.unwrap().changed(&path).is_some());
+        ed.reload();
+        assert_eq!(ed.rows.line(2), "TWO");
+        assert_eq!(ed.cursor, Position { x: 3, y: 2 });
+        assert!(ed.disk.unwrap().changed(&path).is_none());
+    }
+
+    #[test]
+    fn autosave_skips_unnamed_buffers() {
+        let dir = TestDir::new("autosave");
+        let path = dir.join("file.txt");
+        let filename = path.to_string_lossy().to_string();
+        let options = Options {
+            autosave: Autosave::from(String::from("5")),
+            ..Options::default()
+        };
+
+        let mut ed = Editor::new(options).expect("failed to create editor");
+        ed.insert_char('a');
+        ed.open_file(&filename).unwrap();
+        let key = KeyEvent::new(KeyCode::Char('b'), KeyModifiers::NONE);
+        ed.process_event(InputEvent::Key(key));
+        assert!(ed.timers.is_pending(Timer::Autosave));
+
+        ed.switch_buffer(0);
+        ed.autosave();
+        assert_eq!(ed.status_msg, "Autosaved 1 buffer(s)");
+        assert_eq!(ed.current, 0);
+        assert!(ed.dirty > 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "b\n");
+    }
+
+    #[test]
+    fn view_mode_refuses_edits() {
+        let options = Options {
+            read_only: true,
+            ..Options::default()
+        };
+        // the scratch buffer shown when no file is given is read-only too
+        let mut ed = Editor::new(options).expect("failed to create editor");
+        press(&mut ed, KeyCode::Char('x'), KeyModifiers::NONE);
+        assert!(ed.rows.is_empty());
+
+        let mut ed = Editor::with_files(options, &["tests/empty-config-file"])
+            .expect("failed to create editor");
+        assert!(ed.read_only);
+        press(&mut ed, KeyCode::Char('x'), KeyModifiers::NONE);
+        press(&mut ed, KeyCode::Enter, KeyModifiers::NONE);
+        press(&mut ed, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(ed.dirty, 0);
+        assert!(ed.status_msg.starts_with("Buffer is read-only"));
+        assert_eq!(Editor::buffer_flags(ed.dirty, ed.read_only), "[RO]");
+
+        press(&mut ed, KeyCode::Char('r'), KeyModifiers::ALT);
+        press(&mut ed, KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(ed.rows.to_string(), "x\n");
+        assert_eq!(Editor::buffer_flags(ed.dirty, ed.read_only), "(modified)");
+    }
+
+    #[test]
+    fn syntax_files_apply_to_open_buffers() {
+        let mut ed = Editor::with_files(Options::default(), &["tests/script.py", "src/main.rs"])
+            .expect("failed to create editor");
+        let interpreters = |ed: &Editor| ed.get_syntax_data().map(|s| s.interpreters);
+        assert_eq!(
+            interpreters(&ed),
+            Some(vec!["python".into(), "python3".into()])
+        );
+
+        ed.load_syntax_files(&[
+            PathBuf::from("tests/syntax/python.ini"),
+            PathBuf::from("tests/syntax/broken.ini"),
+        ]);
+        assert_eq!(interpreters(&ed), Some(vec!["python".into()]));
+        assert_eq!(ed.hldb[ed.buffers[1].syntax.unwrap()].filetype, "Rust");
+        assert_eq!(
+            ed.status_msg,
+            "Bad syntax file! tests/syntax/broken.ini: unknown flag 'colours'"
+        );
+    }
+
+    #[test]
+    fn filetype_comes_from_shebang_or_by_hand() {
+        let mut ed = editor_with("#!/usr/bin/env -S rustc\nfn main() {}\n");
+        ed.filename = String::from("tests/run");
+        ed.select_syntax_highlight();
+        assert_eq!(ed.get_syntax_data().map(|s| s.filetype), None);
+
+        ed.hldb[1].interpreters.push(String::from("rustc"));
+        ed.select_syntax_highlight();
+        assert_eq!(
+            ed.get_syntax_data().map(|s| s.filetype),
+            Some("Rust".into())
+        );
+
+        ed.set_filetype("c");
+        assert_eq!(ed.get_syntax_data().map(|s| s.filetype), Some("C".into()));
+        ed.set_filetype("cobol");
+        assert_eq!(ed.status_msg, "Unknown filetype 'cobol'");
+        ed.set_filetype("none");
+        assert!(ed.get_syntax_data().is_none());
+        assert_eq!(ed.status_msg, "Filetype set to none");
+    }
+
+    #[test]
+    fn directory_is_an_error() {
+        let err = Editor::with_files(Options::default(), &["tests"])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "tests: is a directory");
+    }
+
+    #[test]
+    fn buffers_keep_their_own_state() {
+        let mut ed = Editor::with_files(
+            Options::default(),
+            &["tests/auto-indent-on", "tests/no-such-file.rs"],
+        )
+        .expect("failed to create editor");
+        assert_eq!(ed.buffers.len(), 2);
+        assert_eq!(ed.filename, "tests/auto-indent-on");
+        assert_eq!(ed.rows.len(), 2);
+
+        ed.cursor = Position { x: 3, y: 1 };
+        ed.cycle_buffer(true);
+        assert_eq!(ed.filename, "tests/no-such-file.rs");
+        assert_eq!(ed.cursor, Position::default());
+        ed.insert_char('x');
+        assert_eq!(ed.unsaved_buffers(), 1);
+
+        ed.cycle_buffer(true);
+        assert_eq!(ed.filename, "tests/auto-indent-on");
+        assert_eq!(ed.cursor, Position { x: 3, y: 1 });
+        assert_eq!(ed.dirty, 0);
+        assert_eq!(ed.unsaved_buffers(), 1);
+
+        ed.close_buffer();
+        assert_eq!(ed.buffers.len(), 1);
+        assert_eq!(ed.rows.to_string(), "x\n");
+        assert_eq!(
+            ed.get_syntax_data().map(|s| s.filetype),
+            Some("Rust".into())
+        );
+    }
+
+    #[test]
+    fn open_prompt_completes_paths() {
+        let mut ed = editor_with("");
+        let mut input = String::from("tests/line");
+        ed.open_callback(&mut input, PromptKey::Complete);
+        assert_eq!(input, "tests/line-numbers-absolute");
+
+        ed.filename = String::from("tests/auto-indent-on");
+        let mut input = String::from("e");
+        ed.open_callback(&mut input, PromptKey::Complete);
+        assert_eq!(input, "empty-config-file");
+    }
+
+    #[test]
+    fn shrinking_a_split_keeps_room_for_the_gutter() {
+        let options = Options {
+            lines: LineNumbers::Absolute,
+            ..Options::default()
+        };
+        let mut ed = Editor::build("one\n", "", options).expect("failed to create editor");
+        ed.process_event(InputEvent::Resize(80, 24));
+        press(&mut ed, KeyCode::Char('v'), KeyModifiers::ALT);
+        ed.focus_window(0);
+        for _ in 0..12 {
+            press(&mut ed, KeyCode::Char('-'), KeyModifiers::ALT);
+        }
+        assert_eq!(ed.status_msg, "Can't resize this window any further");
+
+        let min = ed.min_window_size();
+        for (_, region) in ed.window_regions() {
+            assert!(region.width >= min.width);
+            ed.screen.set_region(region);
+            assert!(ed.screen.bounds().x >= MIN_WINDOW_WIDTH as usize);
+        }
+    }
+
+    #[test]
+    fn split_windows_share_a_buffer() {
+        let mut ed = editor_with("one\ntwo\n");
+        ed.split_window(false);
+        assert_eq!(ed.windows.len(), 2);
+        assert_eq!(ed.active_window, 1);
+
+        ed.cursor = Position { x: 3, y: 1 };
+        ed.insert_char('!');
+        ed.cycle_window();
+        assert_eq!(ed.active_window, 0);
+        assert_eq!(ed.cursor, Position::default());
+        assert_eq!(ed.rows.line(1), "two!");
+
+        // joining the lines leaves the other window's cursor past the end
+        ed.cursor = Position { x: 0, y: 1 };
+        ed.del_char();
+        ed.cycle_window();
+        assert_eq!(ed.rows.line(0), "onetwo!");
+        assert_eq!(ed.cursor, Position { x: 0, y: 1 });
+
+        ed.cycle_window();
+        ed.cursor = Position { x: 2, y: 0 };
+        ed.close_window();
+        assert_eq!(ed.windows.len(), 1);
+        assert_eq!(ed.active_window, 0);
+        assert_eq!(ed.cursor, Position { x: 0, y: 1 });
+    }
+
+    #[test]
+    fn windows_can_show_different_buffers() {
+        let mut ed = Editor::with_files(
+            Options::default(),
+            &["tests/auto-indent-on", "tests/line-numbers-absolute"],
+        )
+        .expect("failed to create editor");
+        ed.split_window(true);
+        ed.cycle_buffer(true);
+        assert_eq!(ed.filename, "tests/line-numbers-absolute");
+
+        ed.focus_neighbour(Direction::Left);
+        assert_eq!(ed.active_window, 0);
+        assert_eq!(ed.filename, "tests/auto-indent-on");
+
+        ed.close_buffer();
+        assert_eq!(ed.filename, "tests/line-numbers-absolute");
+        assert!(ed.windows.iter().all(|w| w.buffer == 0));
+    }
+
+    #[test]
+    fn undo_typing_restores_clean_buffer() {
+        let mut ed = editor_with("fn main() {}\n");
+        for c in "let x".chars() {
+            ed.insert_char(c);
+        }
+        assert_eq!(ed.rows.line(0), "let xfn main() {}");
+        ed.undo();
+        assert_eq!(ed.rows.line(0), "let fn main() {}");
+        ed.undo();
+        assert_eq!(ed.rows.line(0), "fn main() {}");
+        assert_eq!(ed.cursor, Position::default());
+        assert_eq!(ed.dirty, 0);
+    }
+
+    #[test]
+    fn undo_after_converting_line_endings_stays_dirty() {
+        let mut ed = editor_with("abc\n");
+        ed.set_line_ending(LineEnding::CrLf);
+        ed.insert_char('x');
+        ed.undo();
+        assert_eq!(ed.rows.line(0), "abc");
+        assert_eq!(ed.rows.format().line_ending, LineEnding::CrLf);
+        assert!(ed.dirty > 0);
+    }
+
+    #[test]
+    fn redo_reapplies_newline_and_join() {
+        let mut ed = editor_with("abcdef\n");
+        ed.cursor.x = 3;
+        ed.insert_newline();
+        ed.del_char();
+        ed.insert_newline();
+        assert_eq!(ed.rows.len(), 2);
+        ed.undo();
+        ed.undo();
+        assert_eq!(ed.rows.len(), 2);
+        assert_eq!(ed.rows.line(1), "def");
+        ed.undo();
+        assert_eq!(ed.rows.len(), 1);
+        assert_eq!(ed.cursor, Position { x: 3, y: 0 });
+        ed.redo();
+        assert_eq!(ed.rows.line(0), "abc");
+        assert_eq!(ed.cursor, Position { x: 0, y: 1 });
+        assert!(ed.dirty > 0);
+    }
+
+    #[test]
+    fn multiline_edits_round_trip() {
+        let mut ed = editor_with("one\ntwo\nthree\n");
+        let start = Position { x: 1, y: 0 };
+        let end = Position { x: 2, y: 2 };
+        let removed = ed.delete_text(start, end);
+        assert_eq!(removed, "ne\ntwo\nth");
+        assert_eq!(ed.rows.len(), 1);
+        assert_eq!(ed.rows.line(0), "oree");
+        ed.undo();
+        assert_eq!(ed.rows.to_string(), "one\ntwo\nthree\n");
+    }
+
+    fn press(ed: &mut Editor, code: KeyCode, modifiers: KeyModifiers) {
+        ed.process_keypress(KeyEvent::new(code, modifiers));
+    }
+
+    #[test]
+    fn cut_and_paste_across_rows() {
+        let mut ed = editor_with("one\ntwo\nthree\n");
+        ed.cursor.x = 1;
+        press(&mut ed, KeyCode::Down, KeyModifiers::SHIFT);
+        press(&mut ed, KeyCode::Right, KeyModifiers::SHIFT);
+        assert_eq!(
+            ed.selection(),
+            Some((Position { x: 1, y: 0 }, Position { x: 2, y: 1 }))
+        );
+        press(&mut ed, KeyCode::Char('x'), KeyModifiers::CONTROL);
+        assert_eq!(ed.clipboard, "ne\ntw");
+        assert_eq!(ed.rows.to_string(), "oo\nthree\n");
+
+        press(&mut ed, KeyCode::Char('v'), KeyModifiers::CONTROL);
+        assert_eq!(ed.rows.to_string(), "one\ntwo\nthree\n");
+        assert_eq!(ed.cursor, Position { x: 2, y: 1 });
+    }
+
+    #[test]
+    fn replace_confirms_each_match() {
+        let mut ed = editor_with("foo bar foo\nfoofoo\n");
+        let mut answers =
+            vec![ReplaceChoice::Yes, ReplaceChoice::No, ReplaceChoice::All].into_iter();
+        let matcher = Matcher::new("foo", SearchMode::default()).unwrap();
+        let count = ed.replace_matches(&matcher, "x", |_| answers.next().unwrap());
+        assert_eq!(count, 3);
+        assert_eq!(ed.rows.to_string(), "x bar foo\nxx\n");
+        ed.undo();
+        assert_eq!(ed.rows.to_string(), "foo bar foo\nfoofoo\n");
+    }
+
+    #[test]
+    fn replace_stops_on_quit() {
+        let mut ed = editor_with("aaa\n");
+        let matcher = Matcher::new("a", SearchMode::default()).unwrap();
+        let count = ed.replace_matches(&matcher, "bb", |ed| {
+            if ed.cursor.x == 0 {
+                ReplaceChoice::Yes
+            } else {
+                ReplaceChoice::Quit
+            }
+        });
+        assert_eq!(count, 1);
+        assert_eq!(ed.rows.to_string(), "bbaa\n");
+    }
+
+    #[test]
+    fn search_steps_through_every_match() {
+        let mut ed = editor_with("a b a\nb\na\n");
+        ed.find_callback("a", PromptKey::Char);
+        assert_eq!(ed.cursor, Position { x: 0, y: 0 });
+        assert_eq!(ed.prompt_info, "  (match 1 of 3)");
+        ed.find_callback("a", PromptKey::Next);
+        assert_eq!(ed.cursor, Position { x: 4, y: 0 });
+        ed.find_callback("a", PromptKey::Next);
+        assert_eq!(ed.cursor, Position { x: 0, y: 2 });
+        assert_eq!(ed.prompt_info, "  (match 3 of 3)");
+        ed.find_callback("a", PromptKey::Next);
+        assert_eq!(ed.cursor, Position { x: 0, y: 0 });
+        ed.find_callback("a", PromptKey::Prev);
+        assert_eq!(ed.cursor, Position { x: 0, y: 2 });
+    }
+
+    #[test]
+    fn search_in_empty_buffer_finds_nothing() {
+        let mut ed = editor_with("");
+        ed.find_callback("a", PromptKey::Char);
+        assert_eq!(ed.cursor, Position::default());
+        assert_eq!(ed.prompt_info, "  (no matches)");
+    }
+
+    #[test]
+    fn match_count_is_capped() {
+        let mut ed = editor_with(&"x\n".repeat(MAX_MATCH_COUNT + 5));
+        ed.find_callback("x", PromptKey::Char);
+        assert_eq!(ed.prompt_info, "  (match 1 of 1000+)");
+        ed.last_match = Some(Position {
+            x: 0,
+            y: MAX_MATCH_COUNT + 2,
+        });
+        ed.find_callback("x", PromptKey::Next);
+        assert_eq!(ed.prompt_info, "  (1000+ matches)");
+    }
+
+    #[test]
+    fn big_buffers_are_counted_a_chunk_at_a_time() {
+        let mut ed = editor_with(&format!("{}x\n", "a\n".repeat(MATCH_COUNT_LINES + 5)));
+        ed.find_callback("x", PromptKey::Char);
+        assert_eq!(ed.prompt_info, "  (counting matches)");
+        assert!(ed.match_count.is_some());
+        ed.count_more_matches();
+        assert_eq!(ed.prompt_info, "  (match 1 of 1)");
+        assert!(ed.match_count.is_none());
+
+        // leaving the prompt stops the count
+        ed.find_callback("a", PromptKey::Char);
+        assert_eq!(ed.prompt_info, "  (match 1 of 1000+)");
+        ed.find_callback("", PromptKey::Escape);
+        assert!(ed.match_count.is_none());
+    }
+
+    #[test]
+    fn search_highlights_are_cleared_on_escape() {
+        let mut ed = editor_with("xax\naa\n");
+        ed.find_callback("a", PromptKey::Char);
+        ed.scroll();
+        ed.highlight_search_matches();
+        assert_eq!(ed.highlighted_rows, vec![0, 1]);
+        assert!(ed
+            .rows
+            .row(1)
+            .cells()
+            .map(|cell| cell.hl)
+            .all(|h| h == Highlight::OtherMatch));
+
+        ed.find_callback("a", PromptKey::Escape);
+        assert!(ed.highlighted_rows.is_empty());
+        assert!(ed
+            .rows
+            .row(0)
+            .cells()
+            .map(|cell| cell.hl)
+            .all(|h| h == Highlight::Normal));
+        assert!(ed
+            .rows
+            .row(1)
+            .cells()
+            .map(|cell| cell.hl)
+            .all(|h| h == Highlight::Normal));
+    }
+
+    #[test]
+    fn typing_replaces_selection() {
+        let mut ed = editor_with("hello world\n");
+        press(&mut ed, KeyCode::End, KeyModifiers::SHIFT);
+        press(&mut ed, KeyCode::Char('X'), KeyModifiers::SHIFT);
+        assert_eq!(ed.rows.to_string(), "X\n");
+        ed.undo();
+        assert_eq!(ed.rows.to_string(), "hello world\n");
+    }
 }
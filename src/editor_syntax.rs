@@ -1,3 +1,10 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use config::{Config, File, FileFormat};
+
+use crate::row::Highlight;
+
 pub type EditorFlags = u32;
 
 pub mod highlight {
@@ -5,21 +12,96 @@ pub mod highlight {
 
     pub const NUMBERS: EditorFlags = 1 << 0;
     pub const STRINGS: EditorFlags = 1 << 1;
+    pub const LIFETIMES: EditorFlags = 1 << 2; // 'a is a lifetime, not a string
+    pub const RAW_STRINGS: EditorFlags = 1 << 3; // r"..", r#".."#, br".."
+    pub const NESTED_COMMENTS: EditorFlags = 1 << 4;
+    pub const ATTRIBUTES: EditorFlags = 1 << 5; // #[..] and #![..]
+    pub const MACROS: EditorFlags = 1 << 6; // name!
+    pub const MULTILINE_STRINGS: EditorFlags = 1 << 7;
+
+    /* the name a syntax file uses for a flag */
+    pub fn from_name(name: &str) -> Option<EditorFlags> {
+        match name {
+            "numbers" => Some(NUMBERS),
+            "strings" => Some(STRINGS),
+            "lifetimes" => Some(LIFETIMES),
+            "raw_strings" => Some(RAW_STRINGS),
+            "nested_comments" => Some(NESTED_COMMENTS),
+            "attributes" => Some(ATTRIBUTES),
+            "macros" => Some(MACROS),
+            "multiline_strings" => Some(MULTILINE_STRINGS),
+            _ => None,
+        }
+    }
 }
 
+/* a word or symbol a syntax highlights, by class; Todo only counts in comments */
 #[derive(Clone)]
 pub enum Keyword {
     Basic(String),
     Type(String),
+    Constant(String),
+    Builtin(String),
+    Operator(String),
+    Punctuation(String),
+    Todo(String),
+}
+
+impl Keyword {
+    pub fn word(&self) -> &str {
+        match self {
+            Keyword::Basic(word)
+            | Keyword::Type(word)
+            | Keyword::Constant(word)
+            | Keyword::Builtin(word)
+            | Keyword::Operator(word)
+            | Keyword::Punctuation(word)
+            | Keyword::Todo(word) => word,
+        }
+    }
+
+    pub fn highlight(&self) -> Highlight {
+        match self {
+            Keyword::Basic(_) => Highlight::Keyword1,
+            Keyword::Type(_) => Highlight::Keyword2,
+            Keyword::Constant(_) => Highlight::Constant,
+            Keyword::Builtin(_) => Highlight::Builtin,
+            Keyword::Operator(_) => Highlight::Operator,
+            Keyword::Punctuation(_) => Highlight::Punctuation,
+            Keyword::Todo(_) => Highlight::Todo,
+        }
+    }
+
+    /* whether this is made of word characters, rather than symbols like "->" */
+    pub fn is_word(&self) -> bool {
+        self.word()
+            .starts_with(|c: char| c.is_alphanumeric() || c == '_')
+    }
+}
+
+// Picked out in comments unless a syntax file says otherwise
+const TODO_MARKERS: &str = "TODO FIXME XXX";
+
+type KeywordClass = fn(String) -> Keyword;
+
+/* `Keyword`s of one class for each of the space-separated words in `words` */
+fn keyword_list(words: &str, class: KeywordClass) -> Vec<Keyword> {
+    words
+        .split_whitespace()
+        .map(|w| class(w.to_string()))
+        .collect()
 }
 
 #[derive(Clone)]
 pub struct EditorSyntax {
     pub filetype: String,
-    pub filematch: Vec<String>,
+    pub filematch: Vec<String>,    // extensions
+    pub filenames: Vec<String>,    // exact names or glob patterns
+    pub interpreters: Vec<String>, // named by a #! line
     pub singleline_comment_start: Option<String>,
     pub multiline_comment_start: Option<String>,
     pub multiline_comment_end: Option<String>,
+    pub doc_comments: Vec<String>, // comment starts that mark documentation
     pub flags: EditorFlags,
     pub keywords: Vec<Keyword>,
 }
@@ -31,9 +113,12 @@ impl EditorSyntax {
             EditorSyntax {
                 filetype: "C".to_string(),
                 filematch: vec!["c".to_string(), "h".to_string(), "cpp".to_string()],
+                filenames: Vec::new(),
+                interpreters: vec!["tcc".to_string()],
                 singleline_comment_start: Some("//".to_string()),
                 multiline_comment_start: Some("/*".to_string()),
                 multiline_comment_end: Some("*/".to_string()),
+                doc_comments: vec!["/**".to_string(), "///".to_string()],
                 flags: highlight::NUMBERS | highlight::STRINGS,
                 keywords: vec![
                     Keyword::Basic("switch".to_string()),
@@ -59,16 +144,44 @@ impl EditorSyntax {
                     Keyword::Type("unsigned".to_string()),
                     Keyword::Type("signed".to_string()),
                     Keyword::Type("void".to_string()),
-                ],
+                ]
+                .into_iter()
+                .chain(keyword_list("NULL EOF true false", Keyword::Constant))
+                .chain(keyword_list(
+                    "printf fprintf malloc calloc realloc free memcpy memset strlen strcmp",
+                    Keyword::Builtin,
+                ))
+                .chain(keyword_list(
+                    "+ - * / % = ! < > & | ^ ~ ?",
+                    Keyword::Operator,
+                ))
+                .chain(keyword_list("( ) [ ] { } , ; : .", Keyword::Punctuation))
+                .chain(keyword_list(TODO_MARKERS, Keyword::Todo))
+                .collect(),
             },
             // Rust
             EditorSyntax {
                 filetype: "Rust".to_string(),
                 filematch: vec!["rs".to_string()],
+                filenames: Vec::new(),
+                interpreters: vec!["rust-script".to_string()],
                 singleline_comment_start: Some("//".to_string()),
                 multiline_comment_start: Some("/*".to_string()),
                 multiline_comment_end: Some("*/".to_string()),
-                flags: highlight::NUMBERS | highlight::STRINGS,
+                doc_comments: vec![
+                    "///".to_string(),
+                    "//!".to_string(),
+                    "/**".to_string(),
+                    "/*!".to_string(),
+                ],
+                flags: highlight::NUMBERS
+                    | highlight::STRINGS
+                    | highlight::LIFETIMES
+                    | highlight::RAW_STRINGS
+                    | highlight::NESTED_COMMENTS
+                    | highlight::ATTRIBUTES
+                    | highlight::MACROS
+                    | highlight::MULTILINE_STRINGS,
                 keywords: vec![
                     Keyword::Basic("as".to_string()),
                     Keyword::Basic("async".to_string()),
@@ -81,7 +194,6 @@ impl EditorSyntax {
                     Keyword::Basic("else".to_string()),
                     Keyword::Basic("enum".to_string()),
                     Keyword::Basic("extern".to_string()),
-                    Keyword::Basic("false".to_string()),
                     Keyword::Basic("fn".to_string()),
                     Keyword::Basic("for".to_string()),
                     Keyword::Basic("if".to_string()),
@@ -102,7 +214,6 @@ impl EditorSyntax {
                     Keyword::Basic("struct".to_string()),
                     Keyword::Basic("super".to_string()),
                     Keyword::Basic("trait".to_string()),
-                    Keyword::Basic("true".to_string()),
                     Keyword::Basic("type".to_string()),
                     Keyword::Basic("unsafe".to_string()),
                     Keyword::Basic("use".to_string()),
@@ -122,8 +233,544 @@ impl EditorSyntax {
                     Keyword::Type("isize".to_string()),
                     Keyword::Type("f32".to_string()),
                     Keyword::Type("f64".to_string()),
+                ]
+                .into_iter()
+                .chain(keyword_list("true false None", Keyword::Constant))
+                .chain(keyword_list("Some Ok Err drop", Keyword::Builtin))
+                .chain(keyword_list("+ - * / % = ! < > & | ^ ?", Keyword::Operator))
+                .chain(keyword_list("( ) [ ] { } , ; : .", Keyword::Punctuation))
+                .chain(keyword_list(TODO_MARKERS, Keyword::Todo))
+                .collect(),
+            },
+            // Python
+            EditorSyntax {
+                filetype: "Python".to_string(),
+                filematch: vec!["py".to_string(), "pyw".to_string()],
+                filenames: vec!["SConstruct".to_string(), "SConscript".to_string()],
+                interpreters: vec!["python".to_string(), "python3".to_string()],
+                singleline_comment_start: Some("#".to_string()),
+                multiline_comment_start: Some("\"\"\"".to_string()),
+                multiline_comment_end: Some("\"\"\"".to_string()),
+                doc_comments: vec!["#:".to_string()],
+                flags: highlight::NUMBERS | highlight::STRINGS,
+                keywords: keyword_list(
+                    "def class if elif else for while try except finally with as return yield \
+                     import from pass break continue raise lambda global nonlocal del assert \
+                     async await",
+                    Keyword::Basic,
+                )
+                .into_iter()
+                .chain(keyword_list(
+                    "int float str bytes bool list dict set tuple",
+                    Keyword::Type,
+                ))
+                .chain(keyword_list("True False None", Keyword::Constant))
+                .chain(keyword_list(
+                    "print len range open isinstance enumerate zip super self",
+                    Keyword::Builtin,
+                ))
+                .chain(keyword_list(
+                    "and or not in is + - * / % = < > & | ^ ~",
+                    Keyword::Operator,
+                ))
+                .chain(keyword_list("( ) [ ] { } , : .", Keyword::Punctuation))
+                .chain(keyword_list(TODO_MARKERS, Keyword::Todo))
+                .collect(),
+            },
+            // Make
+            EditorSyntax {
+                filetype: "Make".to_string(),
+                filematch: vec!["mk".to_string(), "mak".to_string()],
+                filenames: vec![
+                    "Makefile".to_string(),
+                    "makefile".to_string(),
+                    "GNUmakefile".to_string(),
+                    "Makefile.*".to_string(),
+                ],
+                interpreters: vec!["make".to_string()],
+                singleline_comment_start: Some("#".to_string()),
+                multiline_comment_start: None,
+                multiline_comment_end: None,
+                doc_comments: Vec::new(),
+                flags: highlight::STRINGS,
+                keywords: keyword_list(
+                    "ifeq ifneq ifdef ifndef else endif include define endef export \
+                     unexport override vpath",
+                    Keyword::Basic,
+                )
+                .into_iter()
+                .chain(keyword_list(".PHONY .SUFFIXES .DEFAULT", Keyword::Constant))
+                .chain(keyword_list(
+                    "wildcard patsubst subst shell foreach call filter filter-out dir \
+                     notdir basename addprefix addsuffix",
+                    Keyword::Builtin,
+                ))
+                .chain(keyword_list("= := ::= ?= += != @", Keyword::Operator))
+                .chain(keyword_list("$ ( ) { } :", Keyword::Punctuation))
+                .chain(keyword_list(TODO_MARKERS, Keyword::Todo))
+                .collect(),
+            },
+            // Dockerfile
+            EditorSyntax {
+                filetype: "Dockerfile".to_string(),
+                filematch: vec!["dockerfile".to_string()],
+                filenames: vec![
+                    "Dockerfile".to_string(),
+                    "Containerfile".to_string(),
+                    "Dockerfile.*".to_string(),
                 ],
+                interpreters: Vec::new(),
+                singleline_comment_start: Some("#".to_string()),
+                multiline_comment_start: None,
+                multiline_comment_end: None,
+                doc_comments: Vec::new(),
+                flags: highlight::NUMBERS | highlight::STRINGS,
+                keywords: keyword_list(
+                    "FROM AS RUN CMD LABEL EXPOSE ENV ADD COPY ENTRYPOINT VOLUME USER \
+                     WORKDIR ARG ONBUILD STOPSIGNAL HEALTHCHECK SHELL MAINTAINER",
+                    Keyword::Basic,
+                )
+                .into_iter()
+                .chain(keyword_list("= && ||", Keyword::Operator))
+                .chain(keyword_list("[ ] , $ { }", Keyword::Punctuation))
+                .chain(keyword_list(TODO_MARKERS, Keyword::Todo))
+                .collect(),
             },
         ]
     }
+
+    /* whether a comment or string can carry on from one line to the next */
+    pub fn spans_lines(&self) -> bool {
+        let has_comments =
+            self.multiline_comment_start.is_some() && self.multiline_comment_end.is_some();
+        has_comments || self.flags & (highlight::RAW_STRINGS | highlight::MULTILINE_STRINGS) != 0
+    }
+
+    /*
+     * Reads a syntax definition from an INI file of `key = value` lines:
+     *
+     *   filetype = Python
+     *   filematch = py pyw
+     *   filenames = SConstruct SConscript *.pyi
+     *   interpreters = python python3
+     *   comment = #
+     *   multiline_comment_start = '"""'
+     *   multiline_comment_end = '"""'
+     *   doc_comment = #:
+     *   flags = numbers strings
+     *   keywords = def class if elif else
+     *   types = int str float
+     *   constants = True False None
+     *   builtins = print len range
+     *   operators = + - * / = < > and or not
+     *   punctuation = ( ) [ ] { } , :
+     *   todo = TODO FIXME XXX
+     *
+     * Lists are separated by spaces. At least one of filematch, filenames
+     * or interpreters is needed. Values that start with a quote must be
+     * quoted with the other kind. The flags are numbers, strings, lifetimes,
+     * raw_strings, nested_comments, attributes, macros and multiline_strings.
+     * Without a todo setting the usual TODO, FIXME and XXX markers are used.
+     */
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut settings = Config::builder()
+            .add_source(File::new(&path.to_string_lossy(), FileFormat::Ini))
+            .build()
+            .and_then(|config| config.try_deserialize::<HashMap<String, String>>())
+            .map_err(|e| e.to_string())?;
+
+        let mut take = |key: &str| settings.remove(key).filter(|value| !value.is_empty());
+        let words = |value: Option<String>| -> Vec<String> {
+            value
+                .map(|value| value.split_whitespace().map(String::from).collect())
+                .unwrap_or_default()
+        };
+
+        let filetype = take("filetype").ok_or("missing filetype")?;
+        let filematch = words(take("filematch"));
+        let filenames = words(take("filenames"));
+        let interpreters = words(take("interpreters"));
+        if filematch.is_empty() && filenames.is_empty() && interpreters.is_empty() {
+            return Err(String::from("missing filematch"));
+        }
+        let singleline_comment_start = take("comment");
+        let multiline_comment_start = take("multiline_comment_start");
+        let multiline_comment_end = take("multiline_comment_end");
+        let doc_comments = words(take("doc_comment"));
+        if multiline_comment_start.is_some() != multiline_comment_end.is_some() {
+            return Err(String::from(
+                "multi-line comments need both a start and an end",
+            ));
+        }
+
+        let mut flags = 0;
+        for name in words(take("flags")) {
+            flags |= highlight::from_name(&name).ok_or(format!("unknown flag '{name}'"))?;
+        }
+        let mut keywords = Vec::new();
+        let classes: [(&str, KeywordClass); 7] = [
+            ("keywords", Keyword::Basic),
+            ("types", Keyword::Type),
+            ("constants", Keyword::Constant),
+            ("builtins", Keyword::Builtin),
+            ("operators", Keyword::Operator),
+            ("punctuation", Keyword::Punctuation),
+            ("todo", Keyword::Todo),
+        ];
+        for (key, class) in classes {
+            let list = match take(key) {
+                Some(list) => list,
+                None if key == "todo" => TODO_MARKERS.to_string(),
+                None => continue,
+            };
+            keywords.extend(keyword_list(&list, class));
+        }
+
+        if let Some(key) = settings.keys().next() {
+            return Err(format!("unknown setting '{key}'"));
+        }
+
+        Ok(Self {
+            filetype,
+            filematch,
+            filenames,
+            interpreters,
+            singleline_comment_start,
+            multiline_comment_start,
+            multiline_comment_end,
+            doc_comments,
+            flags,
+            keywords,
+        })
+    }
+}
+
+/* adds loaded definitions in front of hldb, replacing any with the same filetype */
+pub fn merge_syntax(hldb: &mut Vec<EditorSyntax>, syntaxes: Vec<EditorSyntax>) {
+    for syntax in syntaxes.into_iter().rev() {
+        match hldb
+            .iter_mut()
+            .find(|s| s.filetype.eq_ignore_ascii_case(&syntax.filetype))
+        {
+            Some(existing) => *existing = syntax,
+            None => hldb.insert(0, syntax),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Filetype Detection -
+// -----------------------------------------------------------------------------
+
+// How many lines at each end of a file are searched for a modeline
+pub const MODELINE_LINES: usize = 5;
+
+/* picks a syntax by modeline, filename, pattern, extension, then #! interpreter */
+pub fn find_syntax<S: AsRef<str>>(
+    hldb: &[EditorSyntax],
+    filename: &str,
+    head: &[S],
+    tail: &[S],
+) -> Option<usize> {
+    let name = Path::new(filename)
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    // patterns with a '/' in them are matched against the whole path
+    let subject = |pattern: &str| {
+        if pattern.contains('/') {
+            filename
+        } else {
+            &name
+        }
+    };
+    let is_glob = |pattern: &str| pattern.contains(['*', '?', '[']);
+
+    head.iter()
+        .chain(tail)
+        .find_map(|line| modeline_filetype(line.as_ref()))
+        .and_then(|ft| syntax_by_name(hldb, &ft))
+        .or_else(|| {
+            hldb.iter()
+                .position(|s| s.filenames.iter().any(|f| !is_glob(f) && f == subject(f)))
+        })
+        .or_else(|| {
+            hldb.iter().position(|s| {
+                s.filenames
+                    .iter()
+                    .any(|f| is_glob(f) && glob_match(f, subject(f)))
+            })
+        })
+        .or_else(|| {
+            let ext = Path::new(name.as_ref()).extension()?.to_string_lossy();
+            hldb.iter()
+                .position(|s| s.filematch.iter().any(|m| *m == ext))
+        })
+        .or_else(|| {
+            let interpreter = shebang_interpreter(head.first()?.as_ref())?;
+            let unversioned =
+                interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+            syntax_by_name(hldb, &interpreter).or_else(|| syntax_by_name(hldb, unversioned))
+        })
+}
+
+/* the definition a modeline or the user refers to as `name`: a filetype, extension or interpreter */
+pub fn syntax_by_name(hldb: &[EditorSyntax], name: &str) -> Option<usize> {
+    if name.is_empty() {
+        return None;
+    }
+    hldb.iter()
+        .position(|s| s.filetype.eq_ignore_ascii_case(name))
+        .or_else(|| {
+            hldb.iter().position(|s| {
+                s.filematch
+                    .iter()
+                    .chain(&s.interpreters)
+                    .any(|m| m.eq_ignore_ascii_case(name))
+            })
+        })
+}
+
+/* the filetype named by a vim or emacs modeline */
+fn modeline_filetype(line: &str) -> Option<String> {
+    if let Some((_, rest)) = line.split_once("-*-") {
+        let (vars, _) = rest.split_once("-*-")?;
+        if !vars.contains(':') {
+            return Some(vars.trim().to_lowercase());
+        }
+        return vars.split(';').find_map(|var| {
+            let (key, value) = var.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case("mode")
+                .then(|| value.trim().to_lowercase())
+        });
+    }
+
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(idx, _)| *idx == 0 || line[..*idx].ends_with(char::is_whitespace))
+            .map(|(idx, _)| idx + marker.len())
+    })?;
+    line[start..].split([':', ' ', '\t']).find_map(|option| {
+        let (key, value) = option.split_once('=')?;
+        matches!(key, "ft" | "filetype" | "syn" | "syntax").then(|| value.to_string())
+    })
+}
+
+/* the program a "#!" line runs, looking through "/usr/bin/env" */
+fn shebang_interpreter(line: &str) -> Option<String> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let basename = |word: &str| word.rsplit('/').next().unwrap_or(word).to_string();
+    let program = basename(words.next()?);
+    if program != "env" {
+        return Some(program);
+    }
+    // skip env's own options and any VAR=value settings
+    words
+        .find(|word| !word.starts_with('-') && !word.contains('='))
+        .map(basename)
+}
+
+/* matches name against a shell-style pattern using *, ? and [a-z] */
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    // the last '*' seen, and where in `name` it stopped matching
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, len)) = match_class(&pattern[p..], name[n]) {
+                    if matched {
+                        p += len;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == '[' {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        // no match here, so let the last '*' swallow one more character
+        match star {
+            Some((star_p, star_n)) => {
+                star = Some((star_p, star_n + 1));
+                p = star_p + 1;
+                n = star_n + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/* whether `c` is in the "[...]" class starting `pattern`, and the length of the class */
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let end = pattern.iter().skip(2).position(|c| *c == ']')? + 2;
+    let class = &pattern[1..end];
+    let (negated, class) = match class.first() {
+        Some('!' | '^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    Some((matched != negated, end + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin_keywords_have_one_class() {
+        for syntax in EditorSyntax::new() {
+            let mut words: Vec<&str> = syntax.keywords.iter().map(|k| k.word()).collect();
+            let count = words.len();
+            words.sort();
+            words.dedup();
+            assert_eq!(words.len(), count, "{}", syntax.filetype);
+        }
+    }
+
+    #[test]
+    fn loads_a_syntax_file() {
+        let python = EditorSyntax::load(Path::new("tests/syntax/python.ini")).unwrap();
+        assert_eq!(python.filetype, "Python");
+        assert_eq!(python.filematch, vec!["py", "pyw"]);
+        assert_eq!(python.singleline_comment_start.as_deref(), Some("#"));
+        assert_eq!(python.multiline_comment_end.as_deref(), Some("\"\"\""));
+        assert_eq!(python.flags, highlight::NUMBERS | highlight::STRINGS);
+        assert!(matches!(&python.keywords[0], Keyword::Basic(k) if k == "def"));
+        let words = |hl: Highlight| {
+            python
+                .keywords
+                .iter()
+                .filter(|k| k.highlight() == hl)
+                .map(Keyword::word)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(words(Highlight::Keyword2), vec!["int", "float", "str"]);
+        assert_eq!(words(Highlight::Constant), vec!["True", "False", "None"]);
+        assert_eq!(words(Highlight::Operator), vec!["and", "or", "not"]);
+        assert_eq!(words(Highlight::Todo), vec!["TODO", "FIXME", "XXX"]);
+        assert_eq!(python.doc_comments, vec!["#:"]);
+
+        let err = EditorSyntax::load(Path::new("tests/syntax/broken.ini")).err();
+        assert_eq!(err.as_deref(), Some("unknown flag 'colours'"));
+    }
+
+    #[test]
+    fn user_syntax_overrides_and_extends() {
+        let mut hldb = EditorSyntax::new();
+        let mut rust = hldb[1].clone();
+        rust.filetype = String::from("rust");
+        rust.keywords.clear();
+        let python = EditorSyntax::load(Path::new("tests/syntax/python.ini")).unwrap();
+        let mut starlark = python.clone();
+        starlark.filetype = String::from("Starlark");
+
+        merge_syntax(&mut hldb, vec![rust, python, starlark]);
+        let filetypes = hldb.iter().map(|s| s.filetype.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            filetypes,
+            vec!["Starlark", "C", "rust", "Python", "Make", "Dockerfile"]
+        );
+        assert!(hldb[2].keywords.is_empty());
+        assert_eq!(hldb[3].interpreters, vec!["python"]);
+    }
+
+    #[test]
+    fn builtin_syntaxes_detect_filetypes() {
+        let hldb = EditorSyntax::new();
+        let ft = |filename: &str, head: &[&str], tail: &[&str]| {
+            find_syntax(&hldb, filename, head, tail).map(|idx| hldb[idx].filetype.as_str())
+        };
+
+        assert_eq!(ft("src/Makefile", &[], &[]), Some("Make"));
+        assert_eq!(ft("Dockerfile", &[], &[]), Some("Dockerfile"));
+        assert_eq!(ft("Makefile.c", &[], &[]), Some("Make"));
+        assert_eq!(ft("Dockerfile.dev", &[], &[]), Some("Dockerfile"));
+        assert_eq!(ft("lib.c", &[], &[]), Some("C"));
+        assert_eq!(
+            ft("build", &["#!/usr/bin/env python3"], &[]),
+            Some("Python")
+        );
+        assert_eq!(ft("run", &["#!/usr/bin/make -f"], &[]), Some("Make"));
+        assert_eq!(ft("notes", &["x"], &["# vim: ft=python"]), Some("Python"));
+        assert_eq!(ft("notes.c", &["// -*- mode: rust -*-"], &[]), Some("Rust"));
+        assert_eq!(ft("notes", &["#!/bin/sh"], &[]), None);
+    }
+
+    #[test]
+    fn detects_filetypes() {
+        let mut hldb = EditorSyntax::new();
+        let mut make = hldb[0].clone();
+        make.filetype = String::from("Make");
+        make.filematch = vec![String::from("mk")];
+        make.filenames = vec![String::from("Makefile"), String::from("Makefile.*")];
+        let python = EditorSyntax::load(Path::new("tests/syntax/python.ini")).unwrap();
+        merge_syntax(&mut hldb, vec![make, python]);
+        let ft = |filename: &str, head: &[&str], tail: &[&str]| {
+            find_syntax(&hldb, filename, head, tail).map(|idx| hldb[idx].filetype.as_str())
+        };
+
+        assert_eq!(ft("src/main.rs", &[], &[]), Some("Rust"));
+        assert_eq!(ft("dir/Makefile", &[], &[]), Some("Make"));
+        assert_eq!(ft("Makefile.c", &[], &[]), Some("Make"));
+        assert_eq!(ft("SConstruct", &[], &[]), Some("Python"));
+        assert_eq!(
+            ft("build", &["#!/usr/bin/env -S python3.11 -u"], &[]),
+            Some("Python")
+        );
+        assert_eq!(ft("build", &["#!/bin/sh"], &[]), None);
+        assert_eq!(ft("", &["/* vim: set ts=4 ft=rust: */"], &[]), Some("Rust"));
+        assert_eq!(
+            ft("notes.c", &["x"], &["# -*- mode: Python -*-"]),
+            Some("Python")
+        );
+        assert_eq!(ft("notes", &["// -*- c++ -*-"], &[]), None);
+        assert_eq!(ft("notes", &["// -*- cpp -*-"], &[]), Some("C"));
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(glob_match("Makefile*", "Makefile"));
+        assert!(glob_match("*.[ch]", "x.h"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("file?.[!0-4]", "file1.7"));
+        assert!(!glob_match("file?.[!0-4]", "file1.3"));
+        assert!(!glob_match("*.rs", "main.rs.bak"));
+        assert!(!glob_match("?", ""));
+    }
 }
@@ -0,0 +1,279 @@
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Result};
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+const BOM: char = '\u{feff}';
+
+// -----------------------------------------------------------------------------
+//     - Line Endings -
+// -----------------------------------------------------------------------------
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /* the most common line break in `text`, if it has any; CR only counts when
+     * there's no '\n' at all, so stray carriage returns aren't taken as breaks */
+    fn detect(text: &str) -> Option<Self> {
+        let newlines = text.matches('\n').count();
+        let crlfs = text.matches("\r\n").count();
+        if newlines == 0 {
+            return text.contains('\r').then_some(LineEnding::Cr);
+        }
+        Some(if crlfs > newlines - crlfs {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        })
+    }
+}
+
+impl Display for LineEnding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineEnding::Lf => write!(f, "LF"),
+            LineEnding::CrLf => write!(f, "CRLF"),
+            LineEnding::Cr => write!(f, "CR"),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - File Format -
+// -----------------------------------------------------------------------------
+
+/* how a file was laid out on disk, so saving can put it back the same way */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FileFormat {
+    pub encoding: &'static Encoding,
+    pub line_ending: LineEnding,
+    pub bom: bool,
+    pub final_newline: bool,
+}
+
+/* new files are UTF-8 with Unix line endings and a newline at the end */
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            line_ending: LineEnding::Lf,
+            bom: false,
+            final_newline: true,
+        }
+    }
+}
+
+impl Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.encoding.name())?;
+        if self.bom {
+            write!(f, " BOM")?;
+        }
+        write!(f, " {}", self.line_ending)
+    }
+}
+
+impl FileFormat {
+    /* works out the format of `text`, returning it along with the text split on '\n' only */
+    pub fn detect(text: &str) -> (Self, String) {
+        let (bom, text) = match text.strip_prefix(BOM) {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let line_ending = LineEnding::detect(text).unwrap_or_default();
+        let text = match line_ending {
+            LineEnding::Lf => text.to_string(),
+            LineEnding::CrLf => text.replace("\r\n", "\n"),
+            LineEnding::Cr => text.replace('\r', "\n"),
+        };
+        let final_newline = text.is_empty() || text.ends_with('\n');
+        (
+            Self {
+                line_ending,
+                bom,
+                final_newline,
+                ..Default::default()
+            },
+            text,
+        )
+    }
+
+    /* turns text split on '\n' back into what goes on disk */
+    pub fn apply(&self, text: &str) -> String {
+        let text = if self.final_newline {
+            text
+        } else {
+            text.strip_suffix('\n').unwrap_or(text)
+        };
+
+        let mut out = String::with_capacity(text.len() + 3);
+        if self.bom {
+            out.push(BOM);
+        }
+        match self.line_ending {
+            LineEnding::Lf => out.push_str(text),
+            ending => out.push_str(&text.replace('\n', ending.as_str())),
+        }
+        out
+    }
+
+    /* what goes on disk for text split on '\n', in the file's encoding */
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>> {
+        let text = self.apply(text);
+
+        // encoding_rs only decodes UTF-16, so it is written out by hand
+        if self.encoding == UTF_16LE {
+            return Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect());
+        }
+        if self.encoding == UTF_16BE {
+            return Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect());
+        }
+
+        let (bytes, _, unmappable) = self.encoding.encode(&text);
+        if !unmappable {
+            return Ok(bytes.into_owned());
+        }
+
+        let (line, c) = text
+            .split('\n')
+            .enumerate()
+            .find_map(|(y, line)| {
+                line.chars()
+                    .find(|c| self.encoding.encode(c.encode_utf8(&mut [0; 4])).2)
+                    .map(|c| (y + 1, c))
+            })
+            .unwrap_or_default();
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "'{c}' (U+{:04X}) on line {line} can't be written as {}",
+                c as u32,
+                self.encoding.name()
+            ),
+        ))
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Decoding -
+// -----------------------------------------------------------------------------
+
+/* decodes a file (by encoding, BOM or a guess), also saying whether bytes were replaced */
+pub fn decode(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> (String, &'static Encoding, bool) {
+    let encoding = encoding
+        .or_else(|| Encoding::for_bom(bytes).map(|(encoding, _)| encoding))
+        .unwrap_or_else(|| {
+            if std::str::from_utf8(bytes).is_ok() {
+                UTF_8
+            } else {
+                let mut detector = EncodingDetector::new();
+                detector.feed(bytes, true);
+                detector.guess(None, true)
+            }
+        });
+
+    // any byte order mark is kept as U+FEFF, for `FileFormat::detect` to find
+    let (text, malformed) = encoding.decode_without_bom_handling(bytes);
+    (text.into_owned(), encoding, malformed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detects_line_endings() {
+        let (format, text) = FileFormat::detect("one\r\ntwo\r\n");
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert_eq!(text, "one\ntwo\n");
+        assert_eq!(format.apply(&text), "one\r\ntwo\r\n");
+
+        let (format, text) = FileFormat::detect("one\rtwo");
+        assert_eq!(format.line_ending, LineEnding::Cr);
+        assert!(!format.final_newline);
+        assert_eq!(text, "one\ntwo");
+        assert_eq!(format.apply("one\ntwo\n"), "one\rtwo");
+
+        assert_eq!(FileFormat::detect("").0, FileFormat::default());
+    }
+
+    #[test]
+    fn mixed_line_endings_go_by_the_most_common() {
+        let file = "10%\r20%\rdone\nnext line\n";
+        let (format, text) = FileFormat::detect(file);
+        assert_eq!(format.line_ending, LineEnding::Lf);
+        assert_eq!(text, file);
+        assert_eq!(format.apply(&text), file);
+
+        let (format, text) = FileFormat::detect("a\r\nb\r\nc\n");
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert_eq!(text, "a\nb\nc\n");
+        assert!(format.final_newline);
+    }
+
+    #[test]
+    fn keeps_bom_and_missing_newline() {
+        let (format, text) = FileFormat::detect("\u{feff}abc");
+        assert!(format.bom);
+        assert!(!format.final_newline);
+        assert_eq!(text, "abc");
+        assert_eq!(format.apply("abc\n"), "\u{feff}abc");
+
+        let format = FileFormat {
+            line_ending: LineEnding::CrLf,
+            ..format
+        };
+        assert_eq!(format.apply("a\nb\n"), "\u{feff}a\r\nb");
+    }
+
+    #[test]
+    fn round_trips_legacy_encodings() {
+        let (text, encoding, malformed) = decode(b"caf\xe9\n", None);
+        assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+        assert!(!malformed);
+        assert_eq!(text, "caf\u{e9}\n");
+
+        let format = FileFormat {
+            encoding,
+            ..FileFormat::default()
+        };
+        assert_eq!(format.encode(&text).unwrap(), b"caf\xe9\n");
+        let err = format.encode("ok\n\u{3c8}\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'\u{3c8}' (U+03C8) on line 2 can't be written as windows-1252"
+        );
+    }
+
+    #[test]
+    fn round_trips_utf16_with_bom() {
+        let bytes = b"\xff\xfea\x00\r\x00\n\x00";
+        let (text, encoding, _) = decode(bytes, None);
+        assert_eq!(encoding, UTF_16LE);
+
+        let (format, text) = FileFormat::detect(&text);
+        let format = FileFormat { encoding, ..format };
+        assert!(format.bom);
+        assert_eq!(text, "a\n");
+        assert_eq!(format.to_string(), "UTF-16LE BOM CRLF");
+        assert_eq!(format.encode(&text).unwrap(), bytes);
+    }
+}
@@ -1,32 +1,69 @@
-use crossterm::event::{Event::*, KeyEvent, MouseEventKind};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{Event, Event::*, KeyEvent, KeyEventKind, MouseEventKind};
 
 use kilo_ed::*;
 
 pub struct Keyboard;
 
+#[derive(Debug)]
 pub enum InputEvent {
     Key(KeyEvent),
     Resize(u16, u16),
     ScrollUp,
     ScrollDown,
+    FocusGained,
+    FocusLost,
 }
 
 impl Keyboard {
     pub fn read(&self) -> EditorResult<InputEvent, ResultCode> {
         loop {
             if let Ok(event) = crossterm::event::read() {
-                match event {
-                    Key(key_event) => return Ok(InputEvent::Key(key_event)),
-                    Resize(col, row) => return Ok(InputEvent::Resize(col, row)),
-                    Mouse(me) => match me.kind {
-                        MouseEventKind::ScrollUp => return Ok(InputEvent::ScrollUp),
-                        MouseEventKind::ScrollDown => return Ok(InputEvent::ScrollDown),
-                        _ => {}
-                    },
+                if let Some(input) = Keyboard::translate(event) {
+                    return Ok(input);
                 }
             } else {
                 return Err(ResultCode::KeyReadFail);
             }
         }
     }
+
+    /* like `read`, but gives up with None once `timeout` has passed */
+    pub fn read_timeout(&self, timeout: Duration) -> EditorResult<Option<InputEvent>, ResultCode> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match crossterm::event::poll(remaining) {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(_) => return Err(ResultCode::KeyReadFail),
+            }
+            if let Ok(event) = crossterm::event::read() {
+                if let Some(input) = Keyboard::translate(event) {
+                    return Ok(Some(input));
+                }
+            } else {
+                return Err(ResultCode::KeyReadFail);
+            }
+        }
+    }
+
+    fn translate(event: Event) -> Option<InputEvent> {
+        match event {
+            // some terminals report key releases too; only presses matter
+            Key(key_event) if key_event.kind != KeyEventKind::Release => {
+                Some(InputEvent::Key(key_event))
+            }
+            Resize(col, row) => Some(InputEvent::Resize(col, row)),
+            Mouse(me) => match me.kind {
+                MouseEventKind::ScrollUp => Some(InputEvent::ScrollUp),
+                MouseEventKind::ScrollDown => Some(InputEvent::ScrollDown),
+                _ => None,
+            },
+            FocusGained => Some(InputEvent::FocusGained),
+            FocusLost => Some(InputEvent::FocusLost),
+            _ => None,
+        }
+    }
 }
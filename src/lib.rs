@@ -12,22 +12,35 @@ impl std::fmt::Display for ResultCode {
     }
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Position {
-    pub x: u16,
-    pub y: u16,
+    pub x: usize,
+    pub y: usize,
+}
+
+/* positions are ordered the way they appear in the text: by row, then column */
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
 }
 
 impl Position {
     pub fn above(&self, row: usize) -> bool {
-        self.y < row as u16
+        self.y < row
     }
 
     pub fn left_of(&self, col: usize) -> bool {
-        self.x < col as u16
+        self.x < col
     }
 
     pub fn row(&self) -> usize {
-        self.y as usize
+        self.y
     }
 }
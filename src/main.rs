@@ -4,19 +4,32 @@ use config::{builder::DefaultState, Config, ConfigBuilder, File, FileFormat, Val
 use crossterm::Result;
 use xdg::BaseDirectories;
 
+mod buffer;
 mod editor_syntax;
+mod file_format;
 mod keyboard;
 mod options;
+mod prompt;
 mod row;
+mod save;
 mod screen;
+mod search;
+mod swap;
+mod text;
+mod theme;
+mod timer;
+mod undo;
+mod window;
+
+#[cfg(test)]
+mod test_dir;
 
 mod editor;
 use editor::*;
 
-use options::Options;
+use options::{FileEncoding, Options};
 
 fn main() -> Result<()> {
-    let mut args = std::env::args();
     let config_file = BaseDirectories::with_prefix("kilo-ed")?.find_config_file("init");
     let config_builder = default_config();
 
@@ -34,14 +47,62 @@ fn main() -> Result<()> {
         // Failsafe: if the file failed to read, then fall back to the defaults
         default_config().build().unwrap()
     };
-    let options = Options::new(&config);
+    let mut options = Options::new(&config);
+
+    let mut filenames = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--readonly" {
+            options.read_only = true;
+            continue;
+        }
 
-    let mut editor = if args.len() >= 2 {
-        Editor::with_file(options, args.nth(1).unwrap())?
+        // --encoding NAME (or --encoding=NAME) overrides the config file
+        let label = match arg.strip_prefix("--encoding") {
+            Some("") => args.next().unwrap_or_default(),
+            Some(label) if label.starts_with('=') => label[1..].to_string(),
+            _ => {
+                filenames.push(arg);
+                continue;
+            }
+        };
+        options.encoding = FileEncoding::from(label.clone());
+        if options.encoding.0.is_none() {
+            eprintln!("kilo-ed: unknown encoding '{label}'");
+            std::process::exit(1);
+        }
+    }
+
+    let editor = if filenames.is_empty() {
+        Editor::new(options)
     } else {
-        Editor::new(options)?
+        Editor::with_files(options, &filenames)
     };
 
+    let mut editor = match editor {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("kilo-ed: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Ok(dir) = BaseDirectories::with_prefix("kilo-ed")?.create_data_directory("history") {
+        editor.load_prompt_history(&dir);
+    }
+    let mut syntax_files = BaseDirectories::with_prefix("kilo-ed")?
+        .list_config_files_once("syntax")
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ini"))
+        .collect::<Vec<_>>();
+    syntax_files.sort();
+    editor.load_syntax_files(&syntax_files);
+    editor.load_theme(&config);
+
+    if let Ok(dir) = BaseDirectories::with_prefix("kilo-ed")?.create_state_directory("swap") {
+        editor.set_swap_dir(dir);
+    }
+
     editor.start()?;
 
     Ok(())
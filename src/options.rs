@@ -1,8 +1,13 @@
+use std::time::Duration;
+
+use encoding_rs::Encoding;
+
 // -----------------------------------------------------------------------------
 //     - Line Numbers -
 // -----------------------------------------------------------------------------
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
 pub enum LineNumbers {
+    #[default]
     Off,
     Absolute,
     Relative,
@@ -18,20 +23,15 @@ impl From<String> for LineNumbers {
     }
 }
 
-impl Default for LineNumbers {
-    fn default() -> Self {
-        LineNumbers::Off
-    }
-}
-
 impl ConvertOptString for LineNumbers {}
 
 // -----------------------------------------------------------------------------
 //     - Auto-Indentation -
 // -----------------------------------------------------------------------------
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
 pub enum Indentation {
     On,
+    #[default]
     Off,
 }
 
@@ -44,21 +44,16 @@ impl From<String> for Indentation {
     }
 }
 
-impl Default for Indentation {
-    fn default() -> Self {
-        Indentation::Off
-    }
-}
-
 impl ConvertOptString for Indentation {}
 
 // -----------------------------------------------------------------------------
 //     - Line Display-
 // -----------------------------------------------------------------------------
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
 pub enum LineDisplay {
     Wrap,
+    #[default]
     Scroll,
 }
 
@@ -71,13 +66,80 @@ impl From<String> for LineDisplay {
     }
 }
 
-impl Default for LineDisplay {
-    fn default() -> Self {
-        LineDisplay::Scroll
+impl ConvertOptString for LineDisplay {}
+
+// -----------------------------------------------------------------------------
+//     - Backups -
+// -----------------------------------------------------------------------------
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Backup {
+    On,
+    #[default]
+    Off,
+}
+
+impl From<String> for Backup {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "on" => Backup::On,
+            _ => Backup::default(),
+        }
     }
 }
 
-impl ConvertOptString for LineDisplay {}
+impl ConvertOptString for Backup {}
+
+// -----------------------------------------------------------------------------
+//     - Autosave -
+// -----------------------------------------------------------------------------
+
+// How long "on" waits after the last keypress
+const DEFAULT_AUTOSAVE_SECS: u64 = 30;
+
+/* saving without being asked: on "focus", after a number of idle seconds, or both */
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Autosave {
+    pub idle: Option<Duration>,
+    pub on_focus_lost: bool,
+}
+
+impl From<String> for Autosave {
+    fn from(s: String) -> Self {
+        let mut autosave = Autosave::default();
+        for word in s.split([' ', ',']).filter(|word| !word.is_empty()) {
+            match word {
+                "on" => {
+                    autosave.idle = Some(Duration::from_secs(DEFAULT_AUTOSAVE_SECS));
+                    autosave.on_focus_lost = true;
+                }
+                "focus" => autosave.on_focus_lost = true,
+                _ => match word.parse::<u64>() {
+                    Ok(secs) if secs > 0 => autosave.idle = Some(Duration::from_secs(secs)),
+                    _ => return Autosave::default(),
+                },
+            }
+        }
+        autosave
+    }
+}
+
+impl ConvertOptString for Autosave {}
+
+// -----------------------------------------------------------------------------
+//     - File Encoding -
+// -----------------------------------------------------------------------------
+
+/* the encoding files are read and written in (any WHATWG label), or None to detect it */
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct FileEncoding(pub Option<&'static Encoding>);
+
+impl From<String> for FileEncoding {
+    fn from(s: String) -> Self {
+        FileEncoding(Encoding::for_label(s.as_bytes()))
+    }
+}
+
+impl ConvertOptString for FileEncoding {}
 
 // -----------------------------------------------------------------------------
 //     - Options Infrastructure-
@@ -89,6 +151,10 @@ pub struct Options {
     pub lines: LineNumbers,
     pub soft_wrap: LineDisplay,
     pub auto_indent: Indentation,
+    pub encoding: FileEncoding,
+    pub backup: Backup,
+    pub autosave: Autosave,
+    pub read_only: bool, // only set from the command line
 }
 
 impl Options {
@@ -96,11 +162,18 @@ impl Options {
         let lines = read_config_parameter::<LineNumbers>(config, "display", "line_numbers");
         let soft_wrap = read_config_parameter::<LineDisplay>(config, "display", "soft_wrap");
         let auto_indent = read_config_parameter::<Indentation>(config, "display", "auto_indent");
+        let encoding = read_config_parameter::<FileEncoding>(config, "file", "encoding");
+        let backup = read_config_parameter::<Backup>(config, "file", "backup");
+        let autosave = read_config_parameter::<Autosave>(config, "file", "autosave");
 
         Self {
             lines,
             soft_wrap,
             auto_indent,
+            encoding,
+            backup,
+            autosave,
+            read_only: false,
         }
     }
 
@@ -171,4 +244,33 @@ mod test {
         let options = Options::new(&config);
         assert_eq!(options.auto_indent, Indentation::On);
     }
+
+    #[test]
+    fn config_can_set_file_options() {
+        let config = Config::builder()
+            .add_source(File::new("tests/file-options", FileFormat::Ini))
+            .build()
+            .expect("failed to build config");
+        let options = Options::new(&config);
+        assert_eq!(options.encoding.0, Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(options.backup, Backup::On);
+        assert_eq!(
+            options.autosave,
+            Autosave {
+                idle: Some(Duration::from_secs(10)),
+                on_focus_lost: true,
+            }
+        );
+    }
+
+    #[test]
+    fn autosave_parses_its_triggers() {
+        assert_eq!(Autosave::from(String::from("off")), Autosave::default());
+        assert_eq!(Autosave::from(String::from("0")), Autosave::default());
+        assert!(Autosave::from(String::from("focus")).on_focus_lost);
+        assert_eq!(
+            Autosave::from(String::from("on")).idle,
+            Some(Duration::from_secs(DEFAULT_AUTOSAVE_SECS))
+        );
+    }
 }
@@ -0,0 +1,342 @@
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// -----------------------------------------------------------------------------
+//     - Prompt Kinds -
+// -----------------------------------------------------------------------------
+
+/* each kind of prompt keeps its own history */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PromptKind {
+    Search,
+    Replace,
+    SaveAs,
+    Open,
+    Buffer,
+    Filetype,
+}
+
+impl PromptKind {
+    pub const ALL: [PromptKind; 6] = [
+        PromptKind::Search,
+        PromptKind::Replace,
+        PromptKind::SaveAs,
+        PromptKind::Open,
+        PromptKind::Buffer,
+        PromptKind::Filetype,
+    ];
+
+    /* the name of the file its history is kept in */
+    pub fn name(&self) -> &'static str {
+        match self {
+            PromptKind::Search => "search",
+            PromptKind::Replace => "replace",
+            PromptKind::SaveAs => "save-as",
+            PromptKind::Open => "open",
+            PromptKind::Buffer => "buffer",
+            PromptKind::Filetype => "filetype",
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Line Editor -
+// -----------------------------------------------------------------------------
+
+/* the text typed at a prompt; cursor is a byte index on a grapheme boundary */
+#[derive(Default)]
+pub struct LineEditor {
+    text: String,
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /* the screen column of the cursor */
+    pub fn column(&self) -> usize {
+        self.text[..self.cursor].width()
+    }
+
+    /* replaces the whole line, leaving the cursor at the end */
+    pub fn set<T: Into<String>>(&mut self, text: T) {
+        self.text = text.into();
+        self.cursor = self.text.len();
+    }
+
+    pub fn insert_str(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.prev_boundary();
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = self.next_boundary();
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    pub fn backspace(&mut self) -> bool {
+        let start = self.prev_boundary();
+        self.delete_range(start, self.cursor)
+    }
+
+    pub fn delete(&mut self) -> bool {
+        let end = self.next_boundary();
+        self.delete_range(self.cursor, end)
+    }
+
+    /* deletes back to the start of the word before the cursor */
+    pub fn delete_word(&mut self) -> bool {
+        let before = &self.text[..self.cursor];
+        let trimmed = before.trim_end();
+        let start = trimmed
+            .rfind(|c: char| c.is_whitespace() || c == '/')
+            .map_or(0, |idx| idx + 1);
+        // a separator right before the cursor goes on its own
+        let start = if start == self.cursor {
+            self.prev_boundary()
+        } else {
+            start
+        };
+        self.delete_range(start, self.cursor)
+    }
+
+    pub fn delete_to_start(&mut self) -> bool {
+        self.delete_range(0, self.cursor)
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) -> bool {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+        start != end
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |g| self.cursor + g.len())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Prompt History -
+// -----------------------------------------------------------------------------
+
+const MAX_HISTORY: usize = 100;
+
+/* previous answers to a prompt, oldest first, written back to their file if they have one */
+#[derive(Default)]
+pub struct PromptHistory {
+    entries: Vec<String>,
+    pos: Option<usize>,
+    path: Option<PathBuf>,
+}
+
+impl PromptHistory {
+    /* reads a history file, one entry per line; a missing file is an empty history */
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(text) => text.lines().map(String::from).collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            entries,
+            pos: None,
+            path: Some(path),
+        })
+    }
+
+    /* remembers `entry` as the most recent answer, dropping any older copy */
+    pub fn add(&mut self, entry: &str) -> Result<()> {
+        self.pos = None;
+        if entry.is_empty() || entry.contains('\n') {
+            return Ok(());
+        }
+        self.entries.retain(|e| e != entry);
+        self.entries.push(entry.to_string());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.drain(..self.entries.len() - MAX_HISTORY);
+        }
+
+        match &self.path {
+            Some(path) => std::fs::write(path, self.entries.join("\n") + "\n"),
+            None => Ok(()),
+        }
+    }
+
+    /* stops browsing, so the next `older` starts from the newest entry */
+    pub fn reset(&mut self) {
+        self.pos = None;
+    }
+
+    pub fn older(&mut self) -> Option<&str> {
+        let pos = match self.pos {
+            None if self.entries.is_empty() => return None,
+            None => self.entries.len() - 1,
+            Some(pos) => pos.saturating_sub(1),
+        };
+        self.pos = Some(pos);
+        Some(&self.entries[pos])
+    }
+
+    /* moving past the newest entry returns an empty line */
+    pub fn newer(&mut self) -> Option<&str> {
+        let pos = self.pos? + 1;
+        if pos < self.entries.len() {
+            self.pos = Some(pos);
+            Some(&self.entries[pos])
+        } else {
+            self.pos = None;
+            Some("")
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Path Completion -
+// -----------------------------------------------------------------------------
+
+/* completes the last part of a path relative to base, also returning the candidates */
+pub fn complete_path(base: &Path, input: &str) -> (String, Vec<String>) {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(idx) => input.split_at(idx + 1),
+        None => ("", input),
+    };
+
+    let entries = match std::fs::read_dir(base.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return (input.to_string(), Vec::new()),
+    };
+
+    let mut candidates = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let mut name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            if entry.path().is_dir() {
+                name.push('/');
+            }
+            Some(name)
+        })
+        .collect::<Vec<String>>();
+    candidates.sort();
+
+    let common = match candidates.split_first() {
+        Some((first, rest)) => rest.iter().fold(first.as_str(), |common, name| {
+            let len = common
+                .char_indices()
+                .zip(name.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((idx, a), _)| idx + a.len_utf8());
+            &common[..len]
+        }),
+        None => prefix,
+    };
+
+    (format!("{dir}{common}"), candidates)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn history_browses_newest_first() {
+        let mut history = PromptHistory::default();
+        for entry in ["a", "b", "a"] {
+            history.add(entry).unwrap();
+        }
+        assert_eq!(history.older(), Some("a"));
+        assert_eq!(history.older(), Some("b"));
+        assert_eq!(history.older(), Some("b"));
+        assert_eq!(history.newer(), Some("a"));
+        assert_eq!(history.newer(), Some(""));
+        assert_eq!(history.newer(), None);
+    }
+
+    #[test]
+    fn history_survives_reload() {
+        let dir = TestDir::new("history");
+        let path = dir.join(PromptKind::Search.name());
+
+        let mut history = PromptHistory::load(path.clone()).unwrap();
+        history.add("first").unwrap();
+        history.add("second").unwrap();
+
+        let mut history = PromptHistory::load(path).unwrap();
+        assert_eq!(history.older(), Some("second"));
+        assert_eq!(history.older(), Some("first"));
+    }
+
+    #[test]
+    fn line_editor_moves_and_deletes() {
+        let mut line = LineEditor::default();
+        line.insert_str("src/edi\u{308}tor.rs");
+        line.left();
+        line.left();
+        line.left();
+        assert_eq!(line.column(), 10);
+        line.insert_str("_x");
+        assert_eq!(line.as_str(), "src/edi\u{308}tor_x.rs");
+
+        assert!(line.delete_word());
+        assert_eq!(line.as_str(), "src/.rs");
+        assert!(line.delete_word());
+        assert_eq!(line.as_str(), "src.rs");
+        line.end();
+        assert!(line.backspace());
+        line.home();
+        assert!(!line.backspace());
+        assert!(line.delete());
+        assert_eq!(line.as_str(), "rc.r");
+        line.right();
+        assert!(line.delete_to_start());
+        assert_eq!(line.as_str(), "c.r");
+    }
+
+    #[test]
+    fn completes_unique_and_common_prefixes() {
+        let base = Path::new(".");
+        assert_eq!(
+            complete_path(base, "tests/au"),
+            (
+                String::from("tests/auto-indent-on"),
+                vec![String::from("auto-indent-on")]
+            )
+        );
+        assert_eq!(complete_path(base, "sr").0, "src/");
+
+        let (completed, candidates) = complete_path(base, "src/e");
+        assert_eq!(completed, "src/editor");
+        assert!(candidates.len() >= 2);
+        assert_eq!(complete_path(base, "nowhere/x").1, Vec::<String>::new());
+    }
+}
@@ -1,45 +1,126 @@
 use crate::editor_syntax::*;
-use crossterm::style::Color;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 const KILO_TAB_STOP: usize = 8;
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Highlight {
     Normal,
     Number,
     Match,
+    OtherMatch,
     String,
     Comment,
     Keyword1,
     Keyword2,
     MultilineComment,
+    Attribute,
+    Macro,
+    Lifetime,
+    Constant,
+    Builtin,
+    Operator,
+    Punctuation,
+    Escape,
+    DocComment,
+    Todo,
 }
 
 impl Highlight {
-    pub fn syntax_to_color(&self) -> Color {
+    /* every kind of highlight, in the order themes list them */
+    pub const ALL: [Highlight; 19] = [
+        Highlight::Normal,
+        Highlight::Number,
+        Highlight::Match,
+        Highlight::OtherMatch,
+        Highlight::String,
+        Highlight::Comment,
+        Highlight::Keyword1,
+        Highlight::Keyword2,
+        Highlight::MultilineComment,
+        Highlight::Attribute,
+        Highlight::Macro,
+        Highlight::Lifetime,
+        Highlight::Constant,
+        Highlight::Builtin,
+        Highlight::Operator,
+        Highlight::Punctuation,
+        Highlight::Escape,
+        Highlight::DocComment,
+        Highlight::Todo,
+    ];
+
+    /* the name a theme uses for a highlight */
+    pub fn name(&self) -> &'static str {
         match self {
-            Highlight::Normal => Color::White,
-            Highlight::Number => Color::Red,
-            Highlight::Match => Color::Blue,
-            Highlight::String => Color::Magenta,
-            Highlight::Comment => Color::Cyan,
-            Highlight::Keyword1 => Color::Yellow,
-            Highlight::Keyword2 => Color::Green,
-            Highlight::MultilineComment => Color::Cyan,
+            Highlight::Normal => "normal",
+            Highlight::Number => "number",
+            Highlight::Match => "match",
+            Highlight::OtherMatch => "other_match",
+            Highlight::String => "string",
+            Highlight::Comment => "comment",
+            Highlight::Keyword1 => "keyword",
+            Highlight::Keyword2 => "type",
+            Highlight::MultilineComment => "multiline_comment",
+            Highlight::Attribute => "attribute",
+            Highlight::Macro => "macro",
+            Highlight::Lifetime => "lifetime",
+            Highlight::Constant => "constant",
+            Highlight::Builtin => "builtin",
+            Highlight::Operator => "operator",
+            Highlight::Punctuation => "punctuation",
+            Highlight::Escape => "escape",
+            Highlight::DocComment => "doc_comment",
+            Highlight::Todo => "todo",
         }
     }
 
-    pub fn is_normal(&self) -> bool {
-        self == &Highlight::Normal
+    pub fn is_comment(&self) -> bool {
+        matches!(
+            self,
+            Highlight::Comment | Highlight::MultilineComment | Highlight::DocComment
+        )
+    }
+}
+
+/* what's still open at the end of a row: a comment (and its depth) or a string */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LineState {
+    #[default]
+    Code,
+    Comment(usize),
+    DocComment(usize),
+    String(char),
+    RawString(usize),
+}
+
+/* one grapheme of a rendered row, as it appears on screen */
+pub struct Cell<'a> {
+    pub text: &'a str,
+    pub column: usize,
+    pub width: usize,
+    pub hl: Highlight,
+}
+
+/* how many columns a grapheme takes up; control characters are shown as ^X */
+pub fn grapheme_width(grapheme: &str) -> usize {
+    if grapheme.starts_with(|c: char| c.is_ascii_control()) {
+        1
+    } else {
+        // emoji sequences are drawn in a single wide cell
+        grapheme.width().min(2)
     }
 }
 
 pub struct Row {
     pub chars: String,
     pub render: String,
-    hl: Vec<Highlight>,
-    saved_highlight: Vec<Highlight>,
-    pub open_comment: bool,
+    hl: Vec<Highlight>, // indexed by byte offset into `render`
+    // search results drawn over the syntax highlighting, in render columns
+    matches: Vec<(usize, usize)>,
+    current_match: Option<(usize, usize)>,
+    pub end_state: LineState,
 }
 
 impl Row {
@@ -48,286 +129,612 @@ impl Row {
             chars,
             render: String::new(),
             hl: Vec::new(),
-            saved_highlight: Vec::new(),
-            open_comment: false,
+            matches: Vec::new(),
+            current_match: None,
+            end_state: LineState::Code,
         };
 
         result.render_row();
         result
     }
 
-    pub fn render_len(&self) -> usize {
-        self.render.len()
-    }
-
-    pub fn len(&self) -> usize {
-        self.chars.len()
+    /* the number of columns the row takes up on screen */
+    pub fn render_width(&self) -> usize {
+        self.cells()
+            .last()
+            .map_or(0, |cell| cell.column + cell.width)
     }
 
-    pub fn cx_to_rx(&self, cx: u16) -> u16 {
+    /* the screen column of the grapheme that starts at byte `cx` */
+    pub fn cx_to_rx(&self, cx: usize) -> usize {
         let mut rx = 0;
-        for c in self.chars.chars().take(cx as usize) {
-            if c == '\t' {
-                rx += (KILO_TAB_STOP - 1) - (rx % KILO_TAB_STOP);
+        for (idx, g) in self.chars.grapheme_indices(true) {
+            if idx >= cx {
+                break;
+            }
+            if g == "\t" {
+                rx += KILO_TAB_STOP - (rx % KILO_TAB_STOP);
+            } else {
+                rx += grapheme_width(g);
             }
-            rx += 1;
         }
-        rx as u16
+        rx
     }
 
-    pub fn rx_to_cx(&self, rx: usize) -> u16 {
+    /* the byte offset of the grapheme drawn at screen column `rx` (or the end of the row) */
+    pub fn rx_to_cx(&self, rx: usize) -> usize {
         let mut cur_rx = 0;
-        for (cx, c) in self.chars.chars().enumerate() {
-            if c == '\t' {
-                cur_rx += (KILO_TAB_STOP - 1) - (cur_rx % KILO_TAB_STOP);
-            }
-            cur_rx += 1;
+        for (idx, g) in self.chars.grapheme_indices(true) {
+            cur_rx += if g == "\t" {
+                KILO_TAB_STOP - (cur_rx % KILO_TAB_STOP)
+            } else {
+                grapheme_width(g)
+            };
             if cur_rx > rx {
-                return cx as u16;
+                return idx;
             }
         }
-        self.chars.len() as u16
+        self.chars.len()
     }
 
-    pub fn insert_char(&mut self, at: usize, c: char) {
-        if at >= self.chars.len() {
-            self.chars.push(c);
-        } else {
-            self.chars.insert(at, c);
-        }
-        self.render_row();
+    /* the start of the grapheme before byte `cx` */
+    pub fn prev_grapheme(&self, cx: usize) -> usize {
+        self.chars
+            .grapheme_indices(true)
+            .map(|(idx, _)| idx)
+            .take_while(|idx| *idx < cx)
+            .last()
+            .unwrap_or(0)
     }
 
-    /* returns true if row was modified, false otherwise */
-    pub fn del_char(&mut self, at: usize, to_previous_tabstop: bool) -> bool {
-        // 123456
-        if at >= self.chars.len() {
-            false
-        } else {
-            self.chars.remove(at);
-
-            if to_previous_tabstop && at == self.chars.len() {
-                let prev_stop = self.chars.len() - (self.chars.len() % KILO_TAB_STOP);
-                while self.chars.ends_with(' ') && self.chars.len() > prev_stop {
-                    self.chars.pop();
-                }
-            }
+    /* the start of the grapheme after the one at byte `cx` */
+    pub fn next_grapheme(&self, cx: usize) -> usize {
+        self.chars
+            .grapheme_indices(true)
+            .map(|(idx, g)| idx + g.len())
+            .find(|end| *end > cx)
+            .unwrap_or(self.chars.len())
+    }
 
-            self.render_row();
-            true
+    /* moves `cx` back onto a grapheme boundary within the row */
+    pub fn snap(&self, cx: usize) -> usize {
+        if cx >= self.chars.len() {
+            self.chars.len()
+        } else {
+            self.prev_grapheme(cx + 1)
         }
     }
 
-    pub fn split(&mut self, at: usize) -> String {
-        let result = self.chars.split_off(at);
-        self.render_row();
-
-        result
+    /* where a backspace over the character at `at` should start deleting */
+    pub fn del_char_start(&self, at: usize, to_previous_tabstop: bool) -> usize {
+        let mut start = at;
+        if to_previous_tabstop && at + 1 == self.chars.len() {
+            let prev_stop = at - (at % KILO_TAB_STOP);
+            while start > prev_stop && self.chars[..start].ends_with(' ') {
+                start -= 1;
+            }
+        }
+        start
     }
 
     pub fn indent_level(&self) -> usize {
         self.chars.len() - self.chars.trim_start_matches(' ').len()
     }
 
-    pub fn append_string(&mut self, s: &str) {
-        self.chars.push_str(s);
-        self.render_row();
-    }
-
     fn render_row(&mut self) {
         let mut render = String::new();
-        let mut idx = 0;
-        for c in self.chars.chars() {
-            match c {
-                '\t' => {
+        let mut column = 0;
+        for g in self.chars.graphemes(true) {
+            if g == "\t" {
+                render.push(' ');
+                column += 1;
+                while column % KILO_TAB_STOP != 0 {
                     render.push(' ');
-                    idx += 1;
-                    while idx % KILO_TAB_STOP != 0 {
-                        render.push(' ');
-                        idx += 1;
-                    }
-                }
-                _ => {
-                    render.push(c);
-                    idx += 1;
+                    column += 1;
                 }
+            } else {
+                render.push_str(g);
+                column += grapheme_width(g);
             }
         }
 
         self.render = render;
     }
 
-    // returns true if we're in the middle of a multi-line comment
-    pub fn update_syntax(&mut self, ml_comment: bool, syntax: &Option<EditorSyntax>) -> bool {
+    /* highlights the row, which starts in `state`; returns whether the state it ends in changed */
+    pub fn update_syntax(&mut self, state: LineState, syntax: &Option<EditorSyntax>) -> bool {
         self.hl = vec![Highlight::Normal; self.render.len()];
 
         let syntax = if let Some(syntax) = syntax {
             syntax
         } else {
-            return ml_comment;
+            let changed = self.end_state != LineState::Code;
+            self.end_state = LineState::Code;
+            return changed;
         };
 
-        let mut prev_sep = true;
-        let mut row_iter = self.render.chars().enumerate();
-        let mut in_string = None;
-        let mut in_comment = ml_comment;
-        let scs = syntax.singleline_comment_start.as_ref();
-        let mcs = syntax.multiline_comment_start.as_ref();
-        let mce = syntax.multiline_comment_end.as_ref();
-
-        'outer: while let Some((i, c)) = row_iter.next() {
-            let prev_hl = if i > 0 {
-                self.hl[i - 1]
-            } else {
-                Highlight::Normal
+        let flag = |flag: EditorFlags| syntax.flags & flag != 0;
+        let render = self.render.as_str();
+        let hl = &mut self.hl;
+        let mut fill = |at: usize, len: usize, kind: Highlight| hl[at..at + len].fill(kind);
+
+        let mut state = state;
+        let mut i = 0;
+        let scs = syntax.singleline_comment_start.as_deref();
+        let mcs = syntax.multiline_comment_start.as_deref();
+        let mce = syntax.multiline_comment_end.as_deref();
+
+        while let Some(c) = render[i..].chars().next() {
+            let rest = &render[i..];
+            let clen = c.len_utf8();
+
+            match (state, mcs, mce) {
+                (
+                    LineState::Comment(depth) | LineState::DocComment(depth),
+                    Some(mcs),
+                    Some(mce),
+                ) => {
+                    let (kind, nest): (Highlight, fn(usize) -> LineState) = match state {
+                        LineState::DocComment(_) => (Highlight::DocComment, LineState::DocComment),
+                        _ => (Highlight::MultilineComment, LineState::Comment),
+                    };
+                    if flag(highlight::NESTED_COMMENTS) && rest.starts_with(mcs) {
+                        fill(i, mcs.len(), kind);
+                        i += mcs.len();
+                        state = nest(depth + 1);
+                    } else if rest.starts_with(mce) {
+                        fill(i, mce.len(), kind);
+                        i += mce.len();
+                        state = if depth > 1 {
+                            nest(depth - 1)
+                        } else {
+                            LineState::Code
+                        };
+                    } else {
+                        fill(i, clen, kind);
+                        i += clen;
+                    }
+                    continue;
+                }
+                (LineState::String(quote), _, _) => {
+                    if c == '\\' {
+                        let len = escape_len(rest);
+                        fill(i, len, Highlight::Escape);
+                        i += len;
+                        continue;
+                    }
+                    fill(i, clen, Highlight::String);
+                    i += clen;
+                    if c == quote {
+                        state = LineState::Code;
+                    }
+                    continue;
+                }
+                (LineState::RawString(hashes), _, _) => {
+                    let closing = c == '"'
+                        && rest[1..]
+                            .bytes()
+                            .take(hashes)
+                            .filter(|b| *b == b'#')
+                            .count()
+                            == hashes;
+                    let len = if closing { 1 + hashes } else { clen };
+                    fill(i, len, Highlight::String);
+                    i += len;
+                    if closing {
+                        state = LineState::Code;
+                    }
+                    continue;
+                }
+                _ => state = LineState::Code,
+            }
+
+            // whether this is the first character of a word
+            let word_start = !render[..i].chars().next_back().is_some_and(is_ident_char);
+
+            let is_doc = |start: &str| {
+                syntax
+                    .doc_comments
+                    .iter()
+                    .any(|doc| doc.starts_with(start) && rest.starts_with(doc.as_str()))
             };
 
-            if in_string.is_none() && scs.is_some() && !in_comment {
-                if let Some(scs) = scs {
-                    let len = scs.len();
+            if let Some(scs) = scs {
+                if rest.starts_with(scs) {
+                    let kind = if is_doc(scs) {
+                        Highlight::DocComment
+                    } else {
+                        Highlight::Comment
+                    };
+                    fill(i, render.len() - i, kind);
+                    break;
+                }
+            }
 
-                    if self.render.len() - i >= len && &self.render[i..i + len] == scs {
-                        self.hl[i..self.render.len()].fill(Highlight::Comment);
-                        break;
-                    }
+            if let (Some(mcs), Some(mce)) = (mcs, mce) {
+                if let Some(after) = rest.strip_prefix(mcs) {
+                    // "/**/" is an empty comment rather than the start of a doc comment
+                    let empty = after.starts_with(mce);
+                    let (kind, state_now) = if is_doc(mcs) && !empty {
+                        (Highlight::DocComment, LineState::DocComment(1))
+                    } else {
+                        (Highlight::MultilineComment, LineState::Comment(1))
+                    };
+                    fill(i, mcs.len(), kind);
+                    i += mcs.len();
+                    state = state_now;
+                    continue;
                 }
             }
 
-            if mcs.is_some() && mce.is_some() && in_string.is_none() {
-                if in_comment {
-                    self.hl[i] = Highlight::MultilineComment;
-                    if let Some(mce) = mce {
-                        let len = mce.len();
-                        if self.render.len() - i >= len && &self.render[i..i + len] == mce {
-                            self.hl[i..i + len].fill(Highlight::MultilineComment);
-                            row_iter.nth(len - 2);
-                            in_comment = false;
-                            prev_sep = true;
-                        }
+            if flag(highlight::STRINGS) {
+                if flag(highlight::RAW_STRINGS) && word_start {
+                    if let Some((len, hashes)) = raw_string_start(rest) {
+                        fill(i, len, Highlight::String);
+                        i += len;
+                        state = LineState::RawString(hashes);
                         continue;
                     }
-                } else if let Some(mcs) = mcs {
-                    let len = mcs.len();
-                    if self.render.len() - i >= len && &self.render[i..i + len] == mcs {
-                        self.hl[i..i + len].fill(Highlight::MultilineComment);
-                        row_iter.nth(len - 2);
-                        in_comment = true;
+                    // the b of a byte string or byte
+                    if rest.starts_with("b\"") || rest.starts_with("b'") {
+                        fill(i, 1, Highlight::String);
+                        i += 1;
                         continue;
                     }
                 }
-            }
 
-            if syntax.flags & highlight::STRINGS != 0 {
-                if let Some(cur) = in_string {
-                    self.hl[i] = Highlight::String;
-                    if c == '\\' && i + 1 < self.render.len() {
-                        self.hl[i + 1] = Highlight::String;
-                        row_iter.next(); // skip 1
+                if c == '\'' && flag(highlight::LIFETIMES) && !is_char_literal(rest) {
+                    let len = 1 + ident_len(&rest[1..]);
+                    if len > 1 {
+                        fill(i, len, Highlight::Lifetime);
+                        i += len;
                         continue;
                     }
-                    if c == cur {
-                        in_string = None;
-                    }
-                    prev_sep = true;
-                    continue;
                 } else if c == '"' || c == '\'' {
-                    in_string = Some(c);
-                    self.hl[i] = Highlight::String;
+                    fill(i, 1, Highlight::String);
+                    i += 1;
+                    state = LineState::String(c);
                     continue;
                 }
             }
 
-            if (syntax.flags & highlight::NUMBERS) != 0
-                && ((c.is_ascii_digit() && (prev_sep || prev_hl == Highlight::Number))
-                    || (c == '.' && prev_hl == Highlight::Number))
-            {
-                self.hl[i] = Highlight::Number;
-                prev_sep = false;
+            if flag(highlight::NUMBERS) && c.is_ascii_digit() && word_start {
+                let len = number_len(rest);
+                fill(i, len, Highlight::Number);
+                i += len;
                 continue;
             }
 
-            if prev_sep {
-                for keyword in &syntax.keywords {
-                    let (key, is_type_1) = match keyword {
-                        Keyword::Basic(x) => (x, true),
-                        Keyword::Type(x) => (x, false),
-                    };
-
-                    let klen = key.len();
-                    let last_is_sep = if let Some(ch) = self.render.chars().nth(i + klen) {
-                        ch.is_separator()
-                    } else {
-                        true
-                    };
+            if flag(highlight::ATTRIBUTES) && (rest.starts_with("#[") || rest.starts_with("#![")) {
+                let len = attribute_len(rest);
+                fill(i, len, Highlight::Attribute);
+                i += len;
+                continue;
+            }
 
-                    if self.render.len() - i >= klen
-                        && &self.render[i..i + klen] == key
-                        && last_is_sep
-                    {
-                        self.hl[i..i + klen].fill(if is_type_1 {
-                            Highlight::Keyword1
-                        } else {
-                            Highlight::Keyword2
-                        });
-                        row_iter.nth(klen - 2); // skip keyword
-                        prev_sep = false;
-                        continue 'outer;
-                    }
+            if flag(highlight::MACROS) && word_start {
+                let len = ident_len(rest);
+                if len > 0 && rest[len..].starts_with('!') && !rest[len..].starts_with("!=") {
+                    fill(i, len + 1, Highlight::Macro);
+                    i += len + 1;
+                    continue;
                 }
             }
 
-            prev_sep = c.is_separator();
+            // whole words, or the longest of the symbols that starts here
+            let keyword = syntax
+                .keywords
+                .iter()
+                .filter(|keyword| !matches!(keyword, Keyword::Todo(_)))
+                .filter(|keyword| {
+                    let word = keyword.word();
+                    rest.starts_with(word)
+                        && (!keyword.is_word()
+                            || word_start && !rest[word.len()..].starts_with(is_ident_char))
+                })
+                .max_by_key(|keyword| keyword.word().len());
+            if let Some(keyword) = keyword {
+                let len = keyword.word().len();
+                fill(i, len, keyword.highlight());
+                i += len;
+                continue;
+            }
+
+            i += clen;
         }
 
-        let changed = self.open_comment != in_comment;
-        self.open_comment = in_comment;
+        // TODO markers stand out from the comments around them
+        for marker in syntax.keywords.iter().filter_map(|keyword| match keyword {
+            Keyword::Todo(marker) => Some(marker),
+            _ => None,
+        }) {
+            for (at, _) in render.match_indices(marker.as_str()) {
+                let end = at + marker.len();
+                let whole_word = !render[..at].chars().next_back().is_some_and(is_ident_char)
+                    && !render[end..].starts_with(is_ident_char);
+                if whole_word && self.hl[at].is_comment() {
+                    self.hl[at..end].fill(Highlight::Todo);
+                }
+            }
+        }
+
+        // only some languages let a string run on to the next line
+        let state = match state {
+            LineState::String('"') if flag(highlight::MULTILINE_STRINGS) => state,
+            LineState::String(_) => LineState::Code,
+            state => state,
+        };
+        let changed = self.end_state != state;
+        self.end_state = state;
 
         changed
     }
 
-    pub fn iter_highlight(&self, start: usize) -> std::slice::Iter<Highlight> {
-        self.hl[start..].iter()
+    /* the graphemes of the rendered row, with search matches drawn over the syntax colours */
+    pub fn cells(&self) -> impl Iterator<Item = Cell<'_>> + '_ {
+        let within = |column: usize, (start, end): (usize, usize)| start <= column && column < end;
+
+        let mut column = 0;
+        self.render.grapheme_indices(true).map(move |(idx, text)| {
+            let hl = if self.current_match.is_some_and(|m| within(column, m)) {
+                Highlight::Match
+            } else if self.matches.iter().any(|m| within(column, *m)) {
+                Highlight::OtherMatch
+            } else {
+                self.hl.get(idx).copied().unwrap_or(Highlight::Normal)
+            };
+            let width = grapheme_width(text);
+            let cell = Cell {
+                text,
+                column,
+                width,
+                hl,
+            };
+            column += width;
+            cell
+        })
     }
 
     pub fn highlight_match(&mut self, start: usize, len: usize) {
-        self.saved_highlight = self.hl.clone();
-        for c in self.hl[start..start + len].iter_mut() {
-            *c = Highlight::Match;
-        }
+        self.current_match = Some((start, start + len));
+    }
+
+    pub fn highlight_other_matches(&mut self, matches: Vec<(usize, usize)>) {
+        self.matches = matches;
     }
 
     pub fn reset_match(&mut self) {
-        self.hl = self.saved_highlight.clone();
-        self.saved_highlight.clear();
+        self.current_match = None;
+        self.matches.clear();
     }
 }
 
 // -----
 
-trait Separator {
-    fn is_separator(&self) -> bool;
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
 
-impl Separator for char {
-    fn is_separator(&self) -> bool {
-        matches!(
-            self,
-            ' ' | ','
-                | '.'
-                | '('
-                | ')'
-                | '+'
-                | '-'
-                | '/'
-                | '*'
-                | '='
-                | '~'
-                | '%'
-                | '<'
-                | '>'
-                | '['
-                | ']'
-                | ';'
-        )
+/* the length of the identifier `s` starts with, if any */
+fn ident_len(s: &str) -> usize {
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        return 0;
+    }
+    s.find(|c| !is_ident_char(c)).unwrap_or(s.len())
+}
+
+/* the length of the number literal s starts with, including any type suffix */
+fn number_len(s: &str) -> usize {
+    let b = s.as_bytes();
+    let digits = |from: usize, is_digit: fn(&u8) -> bool| {
+        from + b[from..]
+            .iter()
+            .take_while(|c| is_digit(c) || **c == b'_')
+            .count()
+    };
+
+    let end = if b.len() > 2 && b[0] == b'0' && matches!(b[1], b'x' | b'o' | b'b') {
+        digits(2, u8::is_ascii_hexdigit)
+    } else {
+        let mut end = digits(0, u8::is_ascii_digit);
+        // "1.5" but not "1..5" or "1.max(2)"
+        if b.get(end) == Some(&b'.') && b.get(end + 1).is_some_and(u8::is_ascii_digit) {
+            end = digits(end + 1, u8::is_ascii_digit);
+        }
+        if matches!(b.get(end), Some(b'e' | b'E')) {
+            let sign = usize::from(matches!(b.get(end + 1), Some(b'+' | b'-')));
+            if b.get(end + 1 + sign).is_some_and(u8::is_ascii_digit) {
+                end = digits(end + 1 + sign, u8::is_ascii_digit);
+            }
+        }
+        end
+    };
+    end + b[end..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+        .count()
+}
+
+/* what a backslash escape at the start of `s` covers: \n, \x7f, \u{1F600} or \101 */
+fn escape_len(s: &str) -> usize {
+    let b = s.as_bytes();
+    match b.get(1) {
+        None => 1,
+        Some(b'x') => {
+            2 + b[2..]
+                .iter()
+                .take(2)
+                .take_while(|c| c.is_ascii_hexdigit())
+                .count()
+        }
+        Some(b'u') if b.get(2) == Some(&b'{') => s.find('}').map_or(s.len(), |end| end + 1),
+        Some(b'0'..=b'7') => {
+            1 + b[1..]
+                .iter()
+                .take(3)
+                .take_while(|c| (b'0'..=b'7').contains(*c))
+                .count()
+        }
+        Some(_) => 1 + s[1..].chars().next().map_or(0, char::len_utf8),
+    }
+}
+
+/* the length of an r"..", r#".."# or br".." opener at the start of `s`, and its number of '#'s */
+fn raw_string_start(s: &str) -> Option<(usize, usize)> {
+    let prefix = if s.starts_with("br") { 2 } else { 1 };
+    let rest = s.strip_prefix("br").or_else(|| s.strip_prefix('r'))?;
+    let hashes = rest.bytes().take_while(|b| *b == b'#').count();
+    rest[hashes..]
+        .starts_with('"')
+        .then_some((prefix + hashes + 1, hashes))
+}
+
+/* whether the quote `s` starts with opens a character literal like 'x' or '\n' rather than a lifetime */
+fn is_char_literal(s: &str) -> bool {
+    let mut chars = s.chars().skip(1);
+    match chars.next() {
+        Some('\\') => true,
+        Some(_) => chars.next() == Some('\''),
+        None => false,
+    }
+}
+
+/* the length of the attribute `s` starts with, up to its closing ']' or the end of the row */
+fn attribute_len(s: &str) -> usize {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return idx + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wide_and_combining_characters() {
+        let row = Row::new(String::from("a界e\u{301}\tb"));
+        assert_eq!(row.cx_to_rx(1), 1);
+        assert_eq!(row.cx_to_rx(4), 3);
+        assert_eq!(row.cx_to_rx(7), 4);
+        assert_eq!(row.cx_to_rx(8), 8);
+        assert_eq!(row.render_width(), 9);
+
+        assert_eq!(row.next_grapheme(1), 4);
+        assert_eq!(row.next_grapheme(4), 7);
+        assert_eq!(row.prev_grapheme(7), 4);
+        assert_eq!(row.rx_to_cx(2), 1);
+        assert_eq!(row.rx_to_cx(5), 7);
+        assert_eq!(row.snap(5), 4);
+    }
+
+    #[test]
+    fn highlighting_non_ascii_text() {
+        let rust = EditorSyntax::new()
+            .into_iter()
+            .find(|s| s.filetype == "Rust");
+        let mut row = Row::new(String::from("let é = \"ü\"; // ö"));
+        row.update_syntax(LineState::Code, &rust);
+        let hl = row.cells().map(|cell| cell.hl).collect::<Vec<_>>();
+        assert!(hl[..3].iter().all(|h| *h == Highlight::Keyword1));
+        assert!(hl[8..11].iter().all(|h| *h == Highlight::String));
+        assert!(hl[13..].iter().all(|h| *h == Highlight::Comment));
+    }
+
+    #[test]
+    fn highlighting_rust_tokens() {
+        let rust = EditorSyntax::new()
+            .into_iter()
+            .find(|s| s.filetype == "Rust");
+        let highlights = |line: &str| {
+            let mut row = Row::new(String::from(line));
+            row.update_syntax(LineState::Code, &rust);
+            (
+                row.cells().map(|cell| cell.hl).collect::<Vec<_>>(),
+                row.end_state,
+            )
+        };
+        let run = |hl: &[Highlight], kind: Highlight| hl.iter().all(|h| *h == kind);
+
+        let (hl, _) = highlights("fn f<'a>(x: &'a str) -> char { 'x' }");
+        assert!(run(&hl[5..7], Highlight::Lifetime));
+        assert!(run(&hl[13..15], Highlight::Lifetime));
+        assert!(run(&hl[16..19], Highlight::Normal));
+        assert!(run(&hl[31..34], Highlight::String));
+
+        let (hl, _) = highlights("#[derive(Debug)] println!(\"{}\", x != y);");
+        assert!(run(&hl[..16], Highlight::Attribute));
+        assert!(run(&hl[17..25], Highlight::Macro));
+        assert!(run(&hl[31..33], Highlight::Normal));
+        assert!(run(&hl[34..36], Highlight::Operator));
+
+        let (hl, state) = highlights("let n = 0xFF_u8 + 1e-3 + x1; r#\"a \"q\" b");
+        assert!(run(&hl[8..15], Highlight::Number));
+        assert!(run(&hl[18..22], Highlight::Number));
+        assert!(run(&hl[25..27], Highlight::Normal));
+        assert!(run(&hl[29..], Highlight::String));
+        assert_eq!(state, LineState::RawString(1));
+
+        let (hl, state) = highlights("/* a /* b */ c */ 1..2");
+        assert!(run(&hl[..17], Highlight::MultilineComment));
+        assert_eq!(hl[18], Highlight::Number);
+        assert_eq!(hl[21], Highlight::Number);
+        assert_eq!(state, LineState::Code);
+    }
+
+    #[test]
+    fn highlighting_token_classes() {
+        let rust = EditorSyntax::new()
+            .into_iter()
+            .find(|s| s.filetype == "Rust");
+        let highlights = |line: &str, state: LineState| {
+            let mut row = Row::new(String::from(line));
+            row.update_syntax(state, &rust);
+            (
+                row.cells().map(|cell| cell.hl).collect::<Vec<_>>(),
+                row.end_state,
+            )
+        };
+
+        let (hl, _) = highlights("x = Some(true); // TODO: XXXL", LineState::Code);
+        assert_eq!(hl[2], Highlight::Operator);
+        assert!(hl[4..8].iter().all(|h| *h == Highlight::Builtin));
+        assert_eq!(hl[8], Highlight::Punctuation);
+        assert!(hl[9..13].iter().all(|h| *h == Highlight::Constant));
+        assert!(hl[19..23].iter().all(|h| *h == Highlight::Todo));
+        assert!(hl[23..].iter().all(|h| *h == Highlight::Comment));
+
+        let (hl, _) = highlights("\"a\\n\\u{e9}\"", LineState::Code);
+        assert_eq!(hl[1], Highlight::String);
+        assert!(hl[2..4].iter().all(|h| *h == Highlight::Escape));
+        assert!(hl[4..10].iter().all(|h| *h == Highlight::Escape));
+        assert_eq!(hl[10], Highlight::String);
+
+        let (hl, state) = highlights("/// docs", LineState::Code);
+        assert!(hl.iter().all(|h| *h == Highlight::DocComment));
+        assert_eq!(state, LineState::Code);
+        let (hl, state) = highlights("/**/ /** FIXME", LineState::Code);
+        assert!(hl[..4].iter().all(|h| *h == Highlight::MultilineComment));
+        assert!(hl[10..].iter().all(|h| *h == Highlight::Todo));
+        assert_eq!(state, LineState::DocComment(1));
+        let (hl, state) = highlights("end */ 1", state);
+        assert!(hl[..6].iter().all(|h| *h == Highlight::DocComment));
+        assert_eq!(hl[7], Highlight::Number);
+        assert_eq!(state, LineState::Code);
     }
 }
@@ -0,0 +1,185 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Give up on symlinks that point at each other
+const MAX_LINKS: usize = 40;
+
+/* writes a file through a synced temporary file and a rename, following symlinks */
+pub fn save_file(path: &Path, bytes: &[u8], backup: bool) -> Result<()> {
+    let target = resolve_links(path);
+    let permissions = std::fs::metadata(&target).ok().map(|m| m.permissions());
+
+    if backup && permissions.is_some() {
+        std::fs::copy(&target, backup_path(&target))?;
+    }
+
+    let temp = temp_path(&target);
+    let written =
+        write_synced(&temp, bytes, permissions).and_then(|_| std::fs::rename(&temp, &target));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+        return written;
+    }
+
+    // make the rename itself durable; not every platform can sync a directory
+    if let Ok(dir) = File::open(parent_dir(&target)) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/* where the backup of `path` goes */
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push("~");
+    PathBuf::from(name)
+}
+
+fn write_synced(
+    temp: &Path,
+    bytes: &[u8],
+    permissions: Option<std::fs::Permissions>,
+) -> Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    file.write_all(bytes)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    file.sync_all()
+}
+
+/* the file `path` ends up at once every symlink along the way is followed */
+fn resolve_links(path: &Path) -> PathBuf {
+    let mut target = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        match std::fs::read_link(&target) {
+            // a relative link is relative to the directory holding it
+            Ok(link) => target = parent_dir(&target).join(link),
+            Err(_) => break,
+        }
+    }
+    target
+}
+
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    parent_dir(target).join(format!(".{name}.kilo-ed-{}.tmp", std::process::id()))
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Disk State -
+// -----------------------------------------------------------------------------
+
+/* what a file looked like when last read or written, to notice changes by others */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl DiskState {
+    /* the state of the file at `path`, which holds `bytes` */
+    pub fn new(path: &Path, bytes: &[u8]) -> Self {
+        let metadata = std::fs::metadata(path).ok();
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        Self {
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            len: bytes.len() as u64,
+            hash: hasher.finish(),
+        }
+    }
+
+    /* the file's new state if its contents differ from this one (a deleted file hasn't changed) */
+    pub fn changed(&mut self, path: &Path) -> Option<DiskState> {
+        let metadata = std::fs::metadata(path).ok()?;
+        if metadata.modified().ok() == self.modified && metadata.len() == self.len {
+            return None;
+        }
+
+        let current = DiskState::new(path, &std::fs::read(path).ok()?);
+        if current.hash == self.hash {
+            // only touched, so there's no need to read it again next time
+            *self = current;
+            None
+        } else {
+            Some(current)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn replaces_contents_and_keeps_backup() {
+        let dir = TestDir::new("save");
+        let path = dir.join("file.txt");
+        save_file(&path, b"one\n", true).unwrap();
+        assert!(!backup_path(&path).exists());
+
+        save_file(&path, b"two\n", true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"two\n");
+        assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"one\n");
+
+        save_file(&path, b"three\n", false).unwrap();
+        assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"one\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn notices_changed_contents() {
+        let dir = TestDir::new("disk-state");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, b"one\n").unwrap();
+        let mut state = DiskState::new(&path, b"one\n");
+        assert_eq!(state.changed(&path), None);
+
+        std::fs::write(&path, b"one\ntwo\n").unwrap();
+        let changed = state.changed(&path);
+        assert_eq!(changed, Some(DiskState::new(&path, b"one\ntwo\n")));
+
+        let mut state = changed.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state.changed(&path), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_and_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new("save-link");
+        let real = dir.join("real.sh");
+        let link = dir.join("link.sh");
+        std::fs::write(&real, b"old\n").unwrap();
+        std::fs::set_permissions(&real, std::fs::Permissions::from_mode(0o750)).unwrap();
+        std::os::unix::fs::symlink("real.sh", &link).unwrap();
+
+        save_file(&link, b"new\n", false).unwrap();
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(std::fs::read(&real).unwrap(), b"new\n");
+        let mode = std::fs::metadata(&real).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+}
@@ -4,20 +4,28 @@ use std::io::{stdout, Stdout, Write};
 
 use crossterm::{
     cursor,
-    style::{Attribute, Color, Print, SetAttribute, SetForegroundColor},
+    style::{Attribute, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
     terminal, QueueableCommand, Result,
 };
 
 use crate::options::*;
 use crate::row::*;
+use crate::theme::*;
+use crate::window::*;
 use kilo_ed::*;
 
+/* draws into one window's region at a time; width and height are its text area */
 pub struct Screen {
     stdout: Stdout,
+    term_width: u16,
+    term_height: u16,
+    left: u16,
+    top: u16,
     width: u16,
     height: u16,
     gaps: Vec<u16>,
     options: Options,
+    theme: Theme,
     ln_shift: u16,
 }
 
@@ -27,11 +35,16 @@ impl Screen {
     pub fn new(options: Options) -> Result<Self> {
         let (columns, rows) = crossterm::terminal::size()?;
         Ok(Self {
+            term_width: columns,
+            term_height: rows,
+            left: 0,
+            top: 0,
             width: columns,
             height: rows - 2,
             stdout: stdout(),
             gaps: Vec::new(),
             options,
+            theme: Theme::default(),
             ln_shift: if options.lines == LineNumbers::Off {
                 0
             } else {
@@ -48,12 +61,88 @@ impl Screen {
         crossterm::execute!(self.stdout, crossterm::event::DisableMouseCapture)
     }
 
+    /* asks the terminal to say when it gains or loses focus */
+    pub fn report_focus(&mut self) -> Result<()> {
+        crossterm::execute!(self.stdout, crossterm::event::EnableFocusChange)
+    }
+
+    pub fn stop_reporting_focus(&mut self) -> Result<()> {
+        crossterm::execute!(self.stdout, crossterm::event::DisableFocusChange)
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /* switches to drawing in `style`, dropping whatever came before */
+    fn queue_style(&mut self, style: Style) -> Result<()> {
+        self.stdout.queue(SetAttribute(Attribute::Reset))?;
+        if let Some(fg) = style.fg {
+            self.stdout.queue(SetForegroundColor(fg))?;
+        }
+        if let Some(bg) = style.bg {
+            self.stdout.queue(SetBackgroundColor(bg))?;
+        }
+        for (on, attribute) in [
+            (style.bold, Attribute::Bold),
+            (style.italic, Attribute::Italic),
+            (style.dim, Attribute::Dim),
+            (style.underline, Attribute::Underlined),
+            (style.reverse, Attribute::Reverse),
+        ] {
+            if on {
+                self.stdout.queue(SetAttribute(attribute))?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn resize(&mut self, columns: u16, rows: u16) {
-        self.width = columns;
-        self.height = rows - 2;
+        self.term_width = columns;
+        self.term_height = rows;
+        self.set_region(self.text_area());
+    }
+
+    /* the part of the terminal shared out between windows (all but the message bar) */
+    pub fn text_area(&self) -> Rect {
+        Rect {
+            left: 0,
+            top: 0,
+            width: self.term_width,
+            height: self.term_height - 1,
+        }
+    }
+
+    /* directs drawing to a window's region; its last row is the window's status bar */
+    pub fn set_region(&mut self, region: Rect) {
+        self.left = region.left;
+        self.top = region.top;
+        self.width = region.width;
+        self.height = region.height.saturating_sub(1);
+    }
+
+    /* the columns of the current region left for text once the gutter is drawn */
+    fn text_width(&self) -> usize {
+        self.width.saturating_sub(self.ln_shift) as usize
     }
 
-    pub fn draw_rows(&mut self, rows: &[Row], rowoff: u16, coloff: u16, crow: u16) -> Result<()> {
+    pub fn gutter_width(&self) -> u16 {
+        self.ln_shift
+    }
+
+    fn goto(&self, x: u16, y: u16) -> cursor::MoveTo {
+        cursor::MoveTo(self.left + x, self.top + y)
+    }
+
+    /* draws `rows`, the rendered lines starting at `rowoff` */
+    pub fn draw_rows(
+        &mut self,
+        rows: &[&Row],
+        rowoff: usize,
+        coloff: usize,
+        crow: usize,
+        selection: Option<(Position, Position)>,
+    ) -> Result<()> {
         const VERSION: &str = env!("CARGO_PKG_VERSION");
         self.gaps.clear();
         self.gaps.push(0);
@@ -63,48 +152,50 @@ impl Screen {
             if self.options.soft_wrap() && row + gaps >= self.height {
                 break;
             }
-            let filerow = (row + rowoff) as usize;
-            if filerow >= rows.len() {
-                if rows.is_empty() && row == self.height / 3 {
+            let filerow = row as usize + rowoff;
+            if row as usize >= rows.len() {
+                if rows.is_empty() && rowoff == 0 && row == self.height / 3 {
                     let mut welcome = format!("Kilo editor -- version {VERSION}");
                     welcome.truncate(self.width as usize);
                     if welcome.len() < self.width as usize {
                         let leftmost = ((self.width as usize - welcome.len()) / 2) as u16;
+                        let centre = self.goto(leftmost + self.ln_shift, row);
                         self.stdout
-                            .queue(cursor::MoveTo(self.ln_shift, row))?
+                            .queue(self.goto(self.ln_shift, row))?
                             .queue(Print("~".to_string()))?
-                            .queue(cursor::MoveTo(leftmost + self.ln_shift, row))?
+                            .queue(centre)?
                             .queue(Print(welcome))?;
                     } else {
                         self.stdout
-                            .queue(cursor::MoveTo(self.ln_shift, row))?
+                            .queue(self.goto(self.ln_shift, row))?
                             .queue(Print(welcome))?;
                     }
                 } else {
                     self.stdout
-                        .queue(cursor::MoveTo(self.ln_shift, row))?
+                        .queue(self.goto(self.ln_shift, row))?
                         .queue(Print("~".to_string()))?;
                 }
             } else {
                 // Display line number on the left
-                let order = crow.cmp(&(filerow as u16));
+                let order = crow.cmp(&filerow);
                 let gutter_num = if self.options.lines == LineNumbers::Relative {
                     match order {
-                        Ordering::Less => filerow as u16 - crow,
-                        Ordering::Equal => (filerow + 1) as u16,
-                        Ordering::Greater => crow - filerow as u16,
+                        Ordering::Less => filerow - crow,
+                        Ordering::Equal => filerow + 1,
+                        Ordering::Greater => crow - filerow,
                     }
                 } else {
-                    (filerow + 1) as u16
+                    filerow + 1
                 };
 
                 if matches!(
                     self.options.lines,
                     LineNumbers::Absolute | LineNumbers::Relative
                 ) {
+                    let gutter = self.goto(0, row + gaps);
+                    self.queue_style(self.theme.gutter)?;
                     self.stdout
-                        .queue(SetAttribute(Attribute::Reset))?
-                        .queue(cursor::MoveTo(0, row + gaps))?
+                        .queue(gutter)?
                         .queue(
                             if order == Ordering::Equal
                                 && self.options.lines == LineNumbers::Relative
@@ -113,108 +204,121 @@ impl Screen {
                             } else {
                                 Print(format!("{gutter_num:5}"))
                             },
-                        )?;
+                        )?
+                        .queue(SetAttribute(Attribute::Reset))?;
                 }
 
-                let start = if self.options.soft_wrap() {
-                    0
-                } else {
-                    coloff as usize
-                };
-                let end = if !self.options.soft_wrap() {
-                    let mut len = rows[filerow].render_len();
-                    if len < coloff as usize {
-                        continue;
-                    }
-                    len -= coloff as usize;
-                    start
-                        + if len >= (self.width - self.ln_shift) as usize {
-                            (self.width - self.ln_shift) as usize
+                let soft_wrap = self.options.soft_wrap();
+                let start = if soft_wrap { 0 } else { coloff };
+                let avail = self.text_width();
+                if !soft_wrap && rows[row as usize].render_width() < coloff {
+                    continue;
+                }
+
+                let mut current = Style::default();
+
+                // Render columns of this row that fall inside the selection
+                let selected = match selection {
+                    Some((sel_start, sel_end))
+                        if sel_start.row() <= filerow && filerow <= sel_end.row() =>
+                    {
+                        let first = if sel_start.row() == filerow {
+                            rows[row as usize].cx_to_rx(sel_start.x)
                         } else {
-                            len
-                        }
-                } else {
-                    (self.width - self.ln_shift) as usize
+                            0
+                        };
+                        let last = if sel_end.row() == filerow {
+                            rows[row as usize].cx_to_rx(sel_end.x)
+                        } else {
+                            usize::MAX
+                        };
+                        first..last
+                    }
+                    _ => 0..0,
                 };
 
-                let mut hl_iter = rows[filerow].iter_highlight(start);
-                let mut hl = hl_iter.next();
-                let mut current_color = Color::Reset;
-
-                // Draw row in remaining columns
+                // Draw row in remaining columns, wrapping onto extra screen rows
+                // if soft wrap is on
                 let mut screen_row_count = 0;
-                if end > start {
-                    for s in rows[filerow].render[start..]
-                        .chars()
-                        .collect::<Vec<char>>()
-                        .chunks(end - start)
-                        .collect::<Vec<&[char]>>()
-                    {
-                        self.stdout
-                            .queue(cursor::MoveTo(self.ln_shift, row + gaps + screen_row_count))?;
-                        for c in s {
-                            if c.is_ascii_control() {
-                                let sym = (*c as u8 + b'@') as char;
-                                self.stdout
-                                    .queue(SetAttribute(Attribute::Reverse))?
-                                    .queue(Print(sym))?
-                                    .queue(SetAttribute(Attribute::Reset))?;
-                                if current_color != Color::Reset {
-                                    self.stdout.queue(SetForegroundColor(current_color))?;
-                                }
-                            } else {
-                                let highlight = *hl.unwrap();
-                                if highlight.is_normal() {
-                                    if current_color != Color::Reset {
-                                        self.stdout.queue(SetForegroundColor(Color::Reset))?;
-                                        current_color = Color::Reset;
-                                    }
-                                } else {
-                                    let color = highlight.syntax_to_color();
-                                    if color != current_color {
-                                        self.stdout.queue(SetForegroundColor(color))?;
-                                        current_color = color;
-                                    }
-                                }
-                                self.stdout.queue(Print(c))?;
-                                hl = hl_iter.next();
-                            }
+                let mut used = 0;
+                self.stdout.queue(self.goto(self.ln_shift, row + gaps))?;
+                for cell in rows[row as usize].cells() {
+                    if !soft_wrap {
+                        if cell.column < start {
+                            // a wide character cut off by the left edge
+                            let visible = (cell.column + cell.width).saturating_sub(start);
+                            self.stdout.queue(Print(" ".repeat(visible)))?;
+                            used += visible;
+                            continue;
                         }
-                        if !self.options.soft_wrap() {
+                        if used + cell.width > avail {
                             break;
                         }
+                    } else if used + cell.width > avail {
                         screen_row_count += 1;
+                        used = 0;
+                        self.stdout
+                            .queue(self.goto(self.ln_shift, row + gaps + screen_row_count))?;
+                    }
+                    used += cell.width;
+
+                    let mut style = self.theme.highlight(cell.hl);
+                    if selected.contains(&cell.column) {
+                        style = self.theme.selection.over(style);
+                    }
+
+                    if let Some(c) = cell.text.chars().next().filter(char::is_ascii_control) {
+                        let sym = (c as u8 + b'@') as char;
+                        let inverted = Style {
+                            reverse: !style.reverse,
+                            ..style
+                        };
+                        if inverted != current {
+                            self.queue_style(inverted)?;
+                            current = inverted;
+                        }
+                        self.stdout.queue(Print(sym))?;
+                    } else {
+                        if style != current {
+                            self.queue_style(style)?;
+                            current = style;
+                        }
+                        self.stdout.queue(Print(cell.text))?;
                     }
                 }
-                if screen_row_count > 1 {
-                    gaps += screen_row_count - 1;
+                gaps += screen_row_count;
+                if current != Style::default() {
+                    self.stdout.queue(SetAttribute(Attribute::Reset))?;
                 }
-                self.stdout.queue(SetForegroundColor(Color::Reset))?;
             }
             self.gaps.push(gaps);
         }
         Ok(())
     }
 
-    pub fn clear(&mut self, rows: &[Row], rowoff: u16) -> Result<u16> {
+    pub fn clear(&mut self) -> Result<()> {
         self.stdout
             .queue(terminal::Clear(terminal::ClearType::All))?
             .queue(cursor::MoveTo(0, 0))?;
-        Ok(if self.options.soft_wrap() {
+        Ok(())
+    }
+
+    /* how many of `rows` fit in the current region */
+    pub fn visible_rows(&self, rows: &[&Row]) -> usize {
+        if self.options.soft_wrap() {
             let mut count = 0;
             let mut display_height = 0u16;
-            for r in &rows[rowoff as usize..] {
+            for r in rows {
                 count += 1;
-                display_height +=
-                    (r.render_len() / ((self.width - self.ln_shift) as usize) + 1) as u16;
+                display_height += (r.render_width() / self.text_width().max(1) + 1) as u16;
                 if display_height >= self.height {
                     break;
                 }
             }
             count
         } else {
-            self.height
-        })
+            self.height as usize
+        }
     }
 
     pub fn flush(&mut self) -> Result<()> {
@@ -224,34 +328,46 @@ impl Screen {
     pub fn move_to(
         &mut self,
         pos: &Position,
-        render_x: u16,
-        rowoff: u16,
-        coloff: u16,
+        render_x: usize,
+        rowoff: usize,
+        coloff: usize,
     ) -> Result<()> {
-        let display_width = self.width - self.ln_shift;
+        let display_width = self.text_width().max(1);
         let shift_y = render_x / display_width;
 
         let pos_x = if self.options.soft_wrap() {
-            render_x % display_width + self.ln_shift
+            render_x % display_width
         } else {
-            render_x - coloff + self.ln_shift
+            render_x - coloff
         };
 
         let pos_y = if self.options.soft_wrap() {
-            pos.y - rowoff + shift_y + self.gaps[(pos.y - rowoff) as usize]
+            pos.y - rowoff + shift_y + self.gaps[pos.y - rowoff] as usize
         } else {
             pos.y - rowoff
         };
 
-        self.stdout.queue(cursor::MoveTo(pos_x, pos_y))?;
+        self.stdout
+            .queue(self.goto(pos_x as u16 + self.ln_shift, pos_y as u16))?;
 
         Ok(())
     }
 
+    /* puts the cursor on the message bar, e.g. while a prompt is being edited */
+    pub fn move_to_message_bar(&mut self, column: u16) -> Result<()> {
+        self.stdout
+            .queue(cursor::MoveTo(column, self.term_height - 1))?;
+        Ok(())
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
     pub fn bounds(&self) -> Position {
         Position {
-            x: self.width - self.ln_shift,
-            y: self.height - self.gaps.last().unwrap_or(&0),
+            x: self.text_width(),
+            y: (self.height - self.gaps.last().unwrap_or(&0)) as usize,
         }
     }
 
@@ -259,38 +375,59 @@ impl Screen {
         &mut self,
         left: T,
         right: U,
-        help: impl Display,
+        focused: bool,
     ) -> Result<()> {
         let left = left.into();
         let right = right.into();
-
-        let left_width = left.len();
-        let right_width = right.len();
         let screen_width = self.width as usize;
 
-        let status = format!("{left:0$}", left_width.min(screen_width));
-        let mut rstatus = String::new();
-        if right_width < screen_width && status.len() < screen_width - right_width {
-            let mut len = status.len();
-            while len < screen_width {
-                if screen_width - len == right_width {
-                    rstatus.push_str(right.as_str());
-                    break;
-                } else {
-                    rstatus.push(' ');
-                    len += 1;
-                }
-            }
+        let mut status = left.chars().take(screen_width).collect::<String>();
+        let used = status.chars().count();
+        let right_width = right.chars().count();
+        if used + right_width < screen_width {
+            status.push_str(&" ".repeat(screen_width - used - right_width));
+            status.push_str(&right);
+        } else {
+            status.push_str(&" ".repeat(screen_width - used));
         }
 
+        self.stdout.queue(self.goto(0, self.height))?;
+        self.queue_style(if focused {
+            self.theme.status_bar
+        } else {
+            self.theme.inactive_status_bar
+        })?;
+        self.stdout
+            .queue(Print(status))?
+            .queue(SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    pub fn draw_message_bar(&mut self, help: impl Display) -> Result<()> {
+        let screen_width = self.term_width as usize;
+        let help = help
+            .to_string()
+            .chars()
+            .take(screen_width)
+            .collect::<String>();
+
+        self.stdout.queue(cursor::MoveTo(0, self.term_height - 1))?;
+        self.queue_style(self.theme.message_bar)?;
         self.stdout
-            .queue(cursor::MoveTo(0, self.height))?
-            // .queue(SetColors(Colors::new(Color::Black, Color::White)))?
-            .queue(SetAttribute(Attribute::Reverse))?
-            .queue(Print(format!("{status}{rstatus}")))?
-            .queue(cursor::MoveTo(0, self.height + 1))?
             .queue(Print(format!("{help:0$}", screen_width)))?
             .queue(SetAttribute(Attribute::Reset))?;
         Ok(())
     }
+
+    /* the column of bars between two side-by-side windows */
+    pub fn draw_separator(&mut self, separator: Rect) -> Result<()> {
+        for y in separator.top..separator.top + separator.height {
+            self.stdout
+                .queue(cursor::MoveTo(separator.left, y))?
+                .queue(SetAttribute(Attribute::Reverse))?
+                .queue(Print('|'))?
+                .queue(SetAttribute(Attribute::Reset))?;
+        }
+        Ok(())
+    }
 }
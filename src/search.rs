@@ -0,0 +1,219 @@
+use std::fmt::Display;
+
+use regex::{Regex, RegexBuilder};
+
+// Counting stops here, so a search that matches everything stays quick
+pub const MAX_MATCH_COUNT: usize = 1000;
+
+// -----------------------------------------------------------------------------
+//     - Search Mode -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SearchMode {
+    pub regex: bool,
+    pub ignore_case: bool,
+    pub whole_word: bool,
+}
+
+impl SearchMode {
+    /* flips the toggle bound to `key`, returning false if there isn't one */
+    pub fn toggle(&mut self, key: char) -> bool {
+        match key.to_ascii_lowercase() {
+            'r' => self.regex = !self.regex,
+            'c' => self.ignore_case = !self.ignore_case,
+            'w' => self.whole_word = !self.whole_word,
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Display for SearchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags = [
+            (self.regex, "regex"),
+            (self.ignore_case, "nocase"),
+            (self.whole_word, "word"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>();
+
+        if flags.is_empty() {
+            Ok(())
+        } else {
+            write!(f, " [{}]", flags.join(" "))
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Matcher -
+// -----------------------------------------------------------------------------
+pub struct Matcher {
+    re: Regex,
+    expand: bool,
+}
+
+impl Matcher {
+    pub fn new(query: &str, mode: SearchMode) -> Result<Self, regex::Error> {
+        let pattern = if mode.regex {
+            query.to_string()
+        } else {
+            regex::escape(query)
+        };
+        let pattern = if mode.whole_word {
+            format!(r"\b(?:{pattern})\b")
+        } else {
+            pattern
+        };
+
+        Ok(Self {
+            re: RegexBuilder::new(&pattern)
+                .case_insensitive(mode.ignore_case)
+                .build()?,
+            expand: mode.regex,
+        })
+    }
+
+    /* byte range of the first non-empty match starting at or after `start` */
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        let mut pos = start;
+        while pos <= text.len() {
+            let m = self.re.find_at(text, pos)?;
+            if !m.is_empty() {
+                return Some((m.start(), m.end()));
+            }
+            pos = m.end() + text[m.end()..].chars().next().map_or(1, |c| c.len_utf8());
+        }
+        None
+    }
+
+    /* every non-overlapping, non-empty match in `text` */
+    pub fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        let mut pos = 0;
+        while let Some((start, end)) = self.find_at(text, pos) {
+            found.push((start, end));
+            pos = end;
+        }
+        found
+    }
+
+    /* the text that should replace the match at `start..end`, with $n groups expanded */
+    pub fn replacement(&self, text: &str, start: usize, replacement: &str) -> String {
+        if !self.expand {
+            return replacement.to_string();
+        }
+
+        let mut result = String::new();
+        if let Some(caps) = self.re.captures_at(text, start) {
+            caps.expand(replacement, &mut result);
+        }
+        result
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Match Count -
+// -----------------------------------------------------------------------------
+
+/* how many matches there are, worked out a chunk of lines at a time */
+#[derive(Default, Debug, PartialEq)]
+pub struct MatchCount {
+    pub line: usize,  // the next line to count
+    pub index: usize, // which match the current one is, once its line is counted
+    pub total: usize,
+    pub done: bool,
+}
+
+impl MatchCount {
+    /* adds one line's matches; `current` is the current match's column if it's on this line */
+    pub fn add(&mut self, found: &[(usize, usize)], current: Option<usize>) {
+        if let Some(x) = current {
+            self.index = self.total + found.iter().take_while(|m| m.0 < x).count() + 1;
+        }
+        self.total += found.len();
+        self.line += 1;
+    }
+
+    pub fn is_capped(&self) -> bool {
+        self.total >= MAX_MATCH_COUNT
+    }
+
+    /* the count as shown after the search prompt */
+    pub fn info(&self) -> String {
+        let (total, more) = match (self.done, self.is_capped()) {
+            (true, false) if self.total == 0 => return "  (no matches)".to_string(),
+            (false, false) if self.total == 0 => return "  (counting matches)".to_string(),
+            (true, false) => (self.total, ""),
+            (false, false) => (self.total, "+"),
+            (_, true) => (MAX_MATCH_COUNT, "+"),
+        };
+        if self.index > 0 {
+            format!("  (match {} of {total}{more})", self.index)
+        } else {
+            format!("  ({total}{more} matches)")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn match_count_shows_progress() {
+        let mut count = MatchCount::default();
+        assert_eq!(count.info(), "  (counting matches)");
+        count.add(&[(0, 1), (4, 5)], None);
+        assert_eq!(count.info(), "  (2+ matches)");
+        count.add(&[(2, 3)], Some(2));
+        assert_eq!(count.info(), "  (match 3 of 3+)");
+        count.done = true;
+        assert_eq!(count.info(), "  (match 3 of 3)");
+        assert_eq!(
+            MatchCount {
+                done: true,
+                ..Default::default()
+            }
+            .info(),
+            "  (no matches)"
+        );
+    }
+
+    #[test]
+    fn literal_search_escapes_metacharacters() {
+        let m = Matcher::new("a.b", SearchMode::default()).unwrap();
+        assert_eq!(m.find_at("axb a.b", 0), Some((4, 7)));
+    }
+
+    #[test]
+    fn case_and_word_toggles() {
+        let mut mode = SearchMode::default();
+        mode.toggle('c');
+        mode.toggle('w');
+        let m = Matcher::new("let", mode).unwrap();
+        assert_eq!(m.find_at("outlet LET", 0), Some((7, 10)));
+        assert_eq!(mode.to_string(), " [nocase word]");
+    }
+
+    #[test]
+    fn regex_skips_empty_matches_and_expands_groups() {
+        let mode = SearchMode {
+            regex: true,
+            ..Default::default()
+        };
+        let m = Matcher::new("(b*)c", mode).unwrap();
+        assert_eq!(m.find_at("abbc", 0), Some((1, 4)));
+        assert_eq!(m.replacement("abbc", 1, "<$1>"), "<bb>");
+        assert!(Matcher::new("(", mode).is_err());
+    }
+
+    #[test]
+    fn find_all_returns_every_match() {
+        let m = Matcher::new("ab", SearchMode::default()).unwrap();
+        assert_eq!(m.find_all("abxabab"), vec![(0, 2), (3, 5), (5, 7)]);
+    }
+}
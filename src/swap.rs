@@ -0,0 +1,296 @@
+use std::fmt::Display;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use ropey::Rope;
+
+const HEADER: &str = "kilo-ed swap";
+
+// Past this many changed lines (squared) the diff doesn't look for common lines
+const MAX_DIFF_WORK: usize = 4_000_000;
+
+// -----------------------------------------------------------------------------
+//     - Swap Files -
+// -----------------------------------------------------------------------------
+
+/* a buffer's unsaved text, headed by the pid that wrote it and the file it belongs to */
+#[derive(Debug, PartialEq)]
+pub struct Swap {
+    pub pid: u32,
+    pub filename: String,
+    pub text: String,
+}
+
+impl Swap {
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let bad_swap = || Error::new(ErrorKind::InvalidData, "not a kilo-ed swap file");
+
+        let (header, rest) = data.split_once('\n').ok_or_else(bad_swap)?;
+        let (filename, text) = rest.split_once('\n').ok_or_else(bad_swap)?;
+        let pid = header
+            .strip_prefix(HEADER)
+            .and_then(|pid| pid.trim().parse().ok())
+            .ok_or_else(bad_swap)?;
+        Ok(Self {
+            pid,
+            filename: filename.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    /* not synced: swap files are written often, and losing one is no worse than not having it */
+    pub fn write(path: &Path, filename: &str, text: impl Display) -> Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let mut file = BufWriter::new(std::fs::File::create(&temp)?);
+        write!(file, "{HEADER} {}\n{filename}\n{text}", std::process::id())?;
+        file.into_inner().map_err(|e| e.into_error())?;
+        std::fs::rename(&temp, path)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Swap Writer -
+// -----------------------------------------------------------------------------
+
+enum Job {
+    Write(PathBuf, String, Rope),
+    Remove(PathBuf),
+    #[cfg(test)]
+    Sync(Sender<()>),
+}
+
+impl Job {
+    fn run(self) -> Result<()> {
+        match self {
+            Job::Write(path, filename, text) => Swap::write(&path, &filename, text),
+            Job::Remove(path) => match std::fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            #[cfg(test)]
+            Job::Sync(done) => {
+                let _ = done.send(());
+                Ok(())
+            }
+        }
+    }
+}
+
+/* writes and removes swap files in order on a background thread, so big buffers don't stall typing */
+#[derive(Default)]
+pub struct SwapWriter {
+    jobs: Option<Sender<Job>>,
+    errors: Option<Receiver<Error>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SwapWriter {
+    /* `text` is a clone of the buffer's rope, which shares its storage instead of copying it */
+    pub fn write(&mut self, path: PathBuf, filename: &str, text: Rope) {
+        self.send(Job::Write(path, filename.to_string(), text));
+    }
+
+    pub fn remove(&mut self, path: PathBuf) {
+        self.send(Job::Remove(path));
+    }
+
+    /* waits for every write and removal asked for so far */
+    #[cfg(test)]
+    pub fn sync(&mut self) {
+        let (done, wait) = channel();
+        self.send(Job::Sync(done));
+        let _ = wait.recv();
+    }
+
+    /* the oldest error the thread has run into and not yet reported */
+    pub fn take_error(&self) -> Option<Error> {
+        self.errors.as_ref()?.try_recv().ok()
+    }
+
+    /* the thread is only started once there's something for it to do */
+    fn send(&mut self, job: Job) {
+        if self.jobs.is_none() {
+            self.start();
+        }
+        let Some(jobs) = &self.jobs else {
+            // no thread, so the job is done here instead
+            let _ = job.run();
+            return;
+        };
+        if let Err(e) = jobs.send(job) {
+            let _ = e.0.run();
+        }
+    }
+
+    fn start(&mut self) {
+        let (jobs, queue) = channel::<Job>();
+        let (report, errors) = channel();
+        let thread = std::thread::Builder::new()
+            .name(String::from("swap"))
+            .spawn(move || {
+                for job in queue {
+                    if let Err(e) = job.run() {
+                        let _ = report.send(e);
+                    }
+                }
+            });
+        if let Ok(thread) = thread {
+            self.jobs = Some(jobs);
+            self.errors = Some(errors);
+            self.thread = Some(thread);
+        }
+    }
+}
+
+/* lets the queued jobs finish, so a clean exit doesn't leave swap files behind */
+impl Drop for SwapWriter {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/* where the swap file for `filename` is kept within `dir` */
+pub fn swap_path(dir: &Path, filename: &str) -> PathBuf {
+    let path = std::path::absolute(filename).unwrap_or_else(|_| PathBuf::from(filename));
+    let name = path.to_string_lossy().replace(['/', '\\'], "%");
+    dir.join(format!("{name}.swp"))
+}
+
+/* whether `swap` was written after `filename` was last saved */
+pub fn is_newer(swap: &Path, filename: &str) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(swap), modified(Path::new(filename))) {
+        (Some(swap), Some(file)) => swap > file,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Diffs -
+// -----------------------------------------------------------------------------
+
+/* old and new compared line by line, each line marked with '-', '+' or ' ' */
+pub fn diff_lines(old: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut out = String::new();
+    let mut push = |mark: char, line: &str| {
+        out.push(mark);
+        out.push_str(line);
+        out.push('\n');
+    };
+
+    old[..prefix].iter().for_each(|line| push(' ', line));
+    if old_mid.len() * new_mid.len() > MAX_DIFF_WORK {
+        old_mid.iter().for_each(|line| push('-', line));
+        new_mid.iter().for_each(|line| push('+', line));
+    } else {
+        // longest common subsequence, filled in from the end
+        let mut lcs = vec![vec![0usize; new_mid.len() + 1]; old_mid.len() + 1];
+        for i in (0..old_mid.len()).rev() {
+            for j in (0..new_mid.len()).rev() {
+                lcs[i][j] = if old_mid[i] == new_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < old_mid.len() || j < new_mid.len() {
+            if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+                push(' ', old_mid[i]);
+                i += 1;
+                j += 1;
+            } else if j == new_mid.len() || (i < old_mid.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+                push('-', old_mid[i]);
+                i += 1;
+            } else {
+                push('+', new_mid[j]);
+                j += 1;
+            }
+        }
+    }
+    old[old.len() - suffix..]
+        .iter()
+        .for_each(|line| push(' ', line));
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn swap_round_trips() {
+        let dir = TestDir::new("swap");
+        let path = swap_path(&dir, "/some/file.rs");
+        assert_eq!(path, dir.join("%some%file.rs.swp"));
+
+        Swap::write(&path, "/some/file.rs", "one\ntwo\n").unwrap();
+        assert!(is_newer(&path, "/some/file.rs"));
+        assert_eq!(
+            Swap::read(&path).unwrap(),
+            Swap {
+                pid: std::process::id(),
+                filename: String::from("/some/file.rs"),
+                text: String::from("one\ntwo\n"),
+            }
+        );
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(Swap::read(&path).is_err());
+    }
+
+    #[test]
+    fn swap_writer_keeps_jobs_in_order() {
+        let dir = TestDir::new("swapwriter");
+        let path = swap_path(&dir, "/some/file.rs");
+        let mut writer = SwapWriter::default();
+
+        writer.write(path.clone(), "/some/file.rs", Rope::from_str("one\n"));
+        writer.write(path.clone(), "/some/file.rs", Rope::from_str("two\n"));
+        writer.sync();
+        assert_eq!(Swap::read(&path).unwrap().text, "two\n");
+
+        writer.remove(path.clone());
+        writer.sync();
+        assert!(!path.exists());
+        assert!(writer.take_error().is_none());
+
+        writer.write(dir.join("missing").join("x.swp"), "x", Rope::new());
+        writer.sync();
+        assert!(writer.take_error().is_some());
+    }
+
+    #[test]
+    fn diff_marks_changed_lines() {
+        assert_eq!(
+            diff_lines("a\nb\nc\nd\n", "a\nc\nx\nd\n"),
+            " a\n-b\n c\n+x\n d\n"
+        );
+        assert_eq!(diff_lines("", "new\n"), "+new\n");
+    }
+}
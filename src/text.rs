@@ -0,0 +1,304 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use ropey::Rope;
+
+use crate::editor_syntax::*;
+use crate::file_format::*;
+use crate::row::*;
+use kilo_ed::*;
+
+// Cached rows beyond this are dropped once they scroll out of view
+const MAX_CACHED_ROWS: usize = 1000;
+
+/* a buffer's text, kept in a rope; rows are only rendered for the lines that are drawn */
+#[derive(Default)]
+pub struct Text {
+    rope: Rope,
+    rows: HashMap<usize, Row>,
+    end_states: Vec<LineState>,
+    syntax: Option<EditorSyntax>,
+    format: FileFormat,
+}
+
+impl Text {
+    pub fn new(text: &str, syntax: Option<EditorSyntax>) -> Self {
+        let (format, text) = FileFormat::detect(text);
+        let mut rope = Rope::from_str(&text);
+        if !text.is_empty() && !text.ends_with('\n') {
+            rope.insert_char(rope.len_chars(), '\n');
+        }
+        Self {
+            rope,
+            syntax,
+            format,
+            ..Default::default()
+        }
+    }
+
+    pub fn format(&self) -> FileFormat {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: FileFormat) {
+        self.format = format;
+    }
+
+    /* the bytes that should be written to disk, or why they can't be */
+    pub fn to_file(&self) -> std::io::Result<Vec<u8>> {
+        self.format.encode(&self.to_string())
+    }
+
+    /* a copy of the text that shares the rope's storage, so it's cheap to take */
+    pub fn snapshot(&self) -> Rope {
+        self.rope.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rope.len_lines() - 1
+    }
+
+    /* the size of the text in bytes, with '\n' line breaks */
+    pub fn len_bytes(&self) -> usize {
+        self.rope.len_bytes()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rope.len_chars() == 0
+    }
+
+    /* the text of line `y`, without its line break */
+    pub fn line(&self, y: usize) -> Cow<'_, str> {
+        let line = self.rope.line(y);
+        line.slice(..line.len_chars() - 1).into()
+    }
+
+    /* the length of line `y` in bytes */
+    pub fn line_len(&self, y: usize) -> usize {
+        self.rope.line(y).len_bytes() - 1
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> + '_ {
+        (0..self.len()).map(|y| self.line(y))
+    }
+
+    /* everything from `start` up to (but not including) `end`, clamped to the text */
+    pub fn text_range(&self, start: Position, end: Position) -> String {
+        if start.row() >= self.len() || start >= end {
+            return String::new();
+        }
+
+        let end = if end.row() < self.len() {
+            self.byte_index(Position {
+                x: end.x.min(self.line_len(end.y)),
+                y: end.y,
+            })
+        } else {
+            self.rope.len_bytes() - 1
+        };
+        self.rope
+            .byte_slice(self.byte_index(start)..end)
+            .to_string()
+    }
+
+    pub fn insert(&mut self, at: Position, text: &str) {
+        let idx = self.rope.byte_to_char(self.byte_index(at));
+        self.rope.insert(idx, text);
+        self.invalidate(at.row());
+    }
+
+    /* removes `len` bytes starting at `at` */
+    pub fn remove(&mut self, at: Position, len: usize) {
+        let start = self.byte_index(at);
+        let end = (start + len).min(self.rope.len_bytes());
+        let range = self.rope.byte_to_char(start)..self.rope.byte_to_char(end);
+        self.rope.remove(range);
+        self.invalidate(at.row());
+    }
+
+    pub fn insert_row(&mut self, at: usize, text: &str) {
+        let idx = self.rope.line_to_char(at);
+        self.rope.insert(idx, &format!("{text}\n"));
+        self.invalidate(at);
+    }
+
+    pub fn remove_row(&mut self, at: usize) {
+        let range = self.rope.line_to_char(at)..self.rope.line_to_char(at + 1);
+        self.rope.remove(range);
+        self.invalidate(at);
+    }
+
+    pub fn set_syntax(&mut self, syntax: Option<EditorSyntax>) {
+        self.syntax = syntax;
+        self.invalidate(0);
+    }
+
+    /* the rendered row for line `y` */
+    pub fn row(&mut self, y: usize) -> &Row {
+        self.row_mut(y)
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut Row {
+        if !self.rows.contains_key(&y) {
+            let state = self.state_before(y);
+            let row = self.build_row(y, state);
+            self.rows.insert(y, row);
+        }
+        self.rows.get_mut(&y).unwrap()
+    }
+
+    /* the rendered row for line `y`, but only if it has already been built */
+    pub fn cached_mut(&mut self, y: usize) -> Option<&mut Row> {
+        self.rows.get_mut(&y)
+    }
+
+    /* up to `count` rendered rows starting at line `first` */
+    pub fn visible(&mut self, first: usize, count: usize) -> Vec<&Row> {
+        let last = (first + count).min(self.len());
+        if self.rows.len() > MAX_CACHED_ROWS {
+            self.rows.retain(|y, _| (first..last).contains(y));
+        }
+        for y in first..last {
+            self.row_mut(y);
+        }
+        (first..last).map(|y| &self.rows[&y]).collect()
+    }
+
+    fn byte_index(&self, at: Position) -> usize {
+        self.rope.line_to_byte(at.row()) + at.x
+    }
+
+    fn build_row(&mut self, y: usize, state: LineState) -> Row {
+        let mut row = Row::new(self.line(y).into_owned());
+        row.update_syntax(state, &self.syntax);
+        if self.end_states.len() == y {
+            self.end_states.push(row.end_state);
+        }
+        row
+    }
+
+    /* what line `y` starts inside of, such as a multi-line comment */
+    fn state_before(&mut self, y: usize) -> LineState {
+        let spans_lines = self.syntax.as_ref().is_some_and(EditorSyntax::spans_lines);
+        if y == 0 || !spans_lines {
+            return LineState::Code;
+        }
+
+        while self.end_states.len() < y {
+            let idx = self.end_states.len();
+            let state = if idx > 0 {
+                self.end_states[idx - 1]
+            } else {
+                LineState::Code
+            };
+            self.build_row(idx, state);
+        }
+        self.end_states[y - 1]
+    }
+
+    /* forgets everything worked out for line `y` and below */
+    fn invalidate(&mut self, y: usize) {
+        self.end_states.truncate(y);
+        self.rows.retain(|row, _| *row < y);
+    }
+}
+
+impl Display for Text {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in self.rope.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edits_span_lines() {
+        let mut text = Text::new("one\ntwo", None);
+        assert_eq!(text.len(), 2);
+        assert_eq!(text.to_string(), "one\ntwo\n");
+
+        text.insert(Position { x: 1, y: 0 }, "X\nY");
+        assert_eq!(text.to_string(), "oX\nYne\ntwo\n");
+        assert_eq!(
+            text.text_range(Position { x: 1, y: 1 }, Position { x: 9, y: 9 }),
+            "ne\ntwo"
+        );
+
+        text.remove(Position { x: 1, y: 0 }, 3);
+        assert_eq!(text.line(0), "one");
+        text.insert_row(2, "three");
+        text.remove_row(0);
+        assert_eq!(text.lines().collect::<Vec<_>>(), vec!["two", "three"]);
+        assert_eq!(text.line_len(1), 5);
+    }
+
+    #[test]
+    fn only_newlines_break_lines() {
+        let text = Text::new("ab\u{2028}cd\nef\n", None);
+        assert_eq!(text.len(), 2);
+        assert_eq!(text.line(0), "ab\u{2028}cd");
+        assert_eq!(text.line_len(0), 7);
+
+        let text = Text::new("a\x0cb\x0bc\u{85}d\nc\n", None);
+        assert_eq!(text.len(), 2);
+        assert_eq!(text.line(1), "c");
+    }
+
+    #[test]
+    fn saves_in_the_original_format() {
+        let mut text = Text::new("\u{feff}one\r\ntwo", None);
+        assert_eq!(text.line(0), "one");
+        assert_eq!(text.to_string(), "one\ntwo\n");
+
+        text.insert_row(1, "new");
+        assert_eq!(
+            text.to_file().unwrap(),
+            "\u{feff}one\r\nnew\r\ntwo".as_bytes()
+        );
+    }
+
+    #[test]
+    fn comment_state_follows_edits() {
+        let c = EditorSyntax::new().into_iter().find(|s| s.filetype == "C");
+        let mut text = Text::new("int a;\nint b;\nint c;\n", c);
+        assert!(text
+            .row(2)
+            .cells()
+            .map(|cell| cell.hl)
+            .any(|h| h != Highlight::Normal));
+
+        text.insert(Position { x: 0, y: 0 }, "/*");
+        assert!(text
+            .row(2)
+            .cells()
+            .map(|cell| cell.hl)
+            .all(|h| h == Highlight::MultilineComment));
+
+        text.remove(Position { x: 0, y: 0 }, 2);
+        assert!(text
+            .row(2)
+            .cells()
+            .map(|cell| cell.hl)
+            .any(|h| h == Highlight::Keyword2));
+        assert_eq!(text.visible(1, 10).len(), 2);
+    }
+
+    #[test]
+    fn rust_strings_and_comments_span_lines() {
+        let rust = EditorSyntax::new()
+            .into_iter()
+            .find(|s| s.filetype == "Rust");
+        let mut text = Text::new("/* /*\n*/ fn\n*/ r#\"\nfn \"#\nfn\n", rust);
+        let kinds =
+            |text: &mut Text, y: usize| text.row(y).cells().map(|cell| cell.hl).collect::<Vec<_>>();
+        assert_eq!(kinds(&mut text, 1)[3], Highlight::MultilineComment);
+        assert_eq!(kinds(&mut text, 3)[0], Highlight::String);
+        assert_eq!(kinds(&mut text, 4)[0], Highlight::Keyword1);
+    }
+}
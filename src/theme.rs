@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+
+use config::{Config, Value};
+use crossterm::style::Color;
+
+use crate::row::Highlight;
+
+// The theme used when the config doesn't pick one
+const DEFAULT_THEME: &str = "default";
+
+// -----------------------------------------------------------------------------
+//     - Styles -
+// -----------------------------------------------------------------------------
+
+/* how one kind of text is drawn; colours left as None are the terminal's own */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub dim: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub fn fg(color: Color) -> Self {
+        Self {
+            fg: Some(color),
+            ..Default::default()
+        }
+    }
+
+    /* reads a style such as "fg=white bg=4 bold" or "#8a8a8a italic" */
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut style = Style::default();
+        for word in s.split([' ', ',']).filter(|word| !word.is_empty()) {
+            match word {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "dim" => style.dim = true,
+                "underline" => style.underline = true,
+                "reverse" => style.reverse = true,
+                "none" => {}
+                _ => match word.split_once('=') {
+                    Some(("fg", color)) => style.fg = Some(parse_color(color)?),
+                    Some(("bg", color)) => style.bg = Some(parse_color(color)?),
+                    Some((attr, _)) => return Err(format!("unknown attribute '{attr}'")),
+                    None => style.fg = Some(parse_color(word)?),
+                },
+            }
+        }
+        Ok(style)
+    }
+
+    /* this style drawn on top of `base`: colours it sets win, attributes add up */
+    pub fn over(self, base: Style) -> Style {
+        Style {
+            fg: self.fg.or(base.fg),
+            bg: self.bg.or(base.bg),
+            bold: self.bold || base.bold,
+            italic: self.italic || base.italic,
+            dim: self.dim || base.dim,
+            underline: self.underline || base.underline,
+            reverse: self.reverse || base.reverse,
+        }
+    }
+}
+
+/* an ANSI colour name, a 256-colour palette index or "#rrggbb" */
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    let bad_color = || format!("bad colour '{s}'");
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(bad_color());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        return Ok(Color::Rgb {
+            r: channel(0),
+            g: channel(2),
+            b: channel(4),
+        });
+    }
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        return s.parse().map(Color::AnsiValue).map_err(|_| bad_color());
+    }
+    match s {
+        "default" | "reset" => Ok(Color::Reset),
+        "gray" => Ok(Color::Grey),
+        "dark_gray" => Ok(Color::DarkGrey),
+        _ => Color::try_from(s).map_err(|_| bad_color()),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Themes -
+// -----------------------------------------------------------------------------
+
+/* the styles for everything Screen draws */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    highlights: Vec<Style>, // indexed by `Highlight`
+    pub status_bar: Style,
+    pub inactive_status_bar: Style,
+    pub message_bar: Style,
+    pub gutter: Style,
+    pub selection: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::builtin(DEFAULT_THEME).unwrap()
+    }
+}
+
+impl Theme {
+    /* "default" has the original colours, "mono" uses attributes only */
+    pub fn builtin(name: &str) -> Option<Self> {
+        let mut theme = Theme {
+            name: name.to_string(),
+            highlights: vec![Style::default(); Highlight::ALL.len()],
+            status_bar: Style {
+                reverse: true,
+                bold: true,
+                ..Default::default()
+            },
+            inactive_status_bar: Style {
+                reverse: true,
+                ..Default::default()
+            },
+            message_bar: Style {
+                reverse: true,
+                ..Default::default()
+            },
+            gutter: Style::default(),
+            selection: Style {
+                reverse: true,
+                ..Default::default()
+            },
+        };
+
+        let styles = match name {
+            "default" => vec![
+                (Highlight::Number, Style::fg(Color::Red)),
+                (Highlight::Match, Style::fg(Color::Blue)),
+                (Highlight::OtherMatch, Style::fg(Color::DarkYellow)),
+                (Highlight::String, Style::fg(Color::Magenta)),
+                (Highlight::Comment, Style::fg(Color::Cyan)),
+                (Highlight::Keyword1, Style::fg(Color::Yellow)),
+                (Highlight::Keyword2, Style::fg(Color::Green)),
+                (Highlight::MultilineComment, Style::fg(Color::Cyan)),
+                (Highlight::Attribute, Style::fg(Color::DarkCyan)),
+                (Highlight::Macro, Style::fg(Color::DarkMagenta)),
+                (Highlight::Lifetime, Style::fg(Color::DarkGreen)),
+                (Highlight::Constant, Style::fg(Color::DarkRed)),
+                (Highlight::Builtin, Style::fg(Color::DarkBlue)),
+                (Highlight::Operator, Style::fg(Color::White)),
+                (Highlight::Punctuation, Style::fg(Color::Grey)),
+                (Highlight::Escape, Style::fg(Color::AnsiValue(208))),
+                (Highlight::DocComment, Style::fg(Color::AnsiValue(73))),
+                (
+                    Highlight::Todo,
+                    Style {
+                        bg: Some(Color::Yellow),
+                        bold: true,
+                        ..Style::fg(Color::Black)
+                    },
+                ),
+            ],
+            "mono" => {
+                let bold = Style {
+                    bold: true,
+                    ..Default::default()
+                };
+                let italic = Style {
+                    italic: true,
+                    ..Default::default()
+                };
+                let underline = Style {
+                    underline: true,
+                    ..Default::default()
+                };
+                let dim = Style {
+                    dim: true,
+                    ..Default::default()
+                };
+                vec![
+                    (
+                        Highlight::Number,
+                        Style {
+                            italic: true,
+                            ..underline
+                        },
+                    ),
+                    (
+                        Highlight::Match,
+                        Style {
+                            reverse: true,
+                            ..underline
+                        },
+                    ),
+                    (Highlight::OtherMatch, underline),
+                    (Highlight::String, underline),
+                    (Highlight::Comment, italic),
+                    (Highlight::Keyword1, bold),
+                    (Highlight::Keyword2, bold),
+                    (Highlight::MultilineComment, italic),
+                    (Highlight::Attribute, italic),
+                    (Highlight::Macro, bold),
+                    (Highlight::Lifetime, italic),
+                    (
+                        Highlight::Constant,
+                        Style {
+                            bold: true,
+                            ..underline
+                        },
+                    ),
+                    (
+                        Highlight::Builtin,
+                        Style {
+                            bold: true,
+                            ..italic
+                        },
+                    ),
+                    (Highlight::Operator, bold),
+                    (Highlight::Punctuation, dim),
+                    (
+                        Highlight::Escape,
+                        Style {
+                            dim: true,
+                            ..underline
+                        },
+                    ),
+                    (Highlight::DocComment, italic),
+                    (
+                        Highlight::Todo,
+                        Style {
+                            reverse: true,
+                            ..bold
+                        },
+                    ),
+                ]
+            }
+            _ => return None,
+        };
+        for (hl, style) in styles {
+            theme.highlights[hl as usize] = style;
+        }
+        Some(theme)
+    }
+
+    pub fn highlight(&self, hl: Highlight) -> Style {
+        self.highlights[hl as usize]
+    }
+
+    /* the theme named in [display], built from its [theme.NAME] section if there is one */
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let name = config
+            .get_string("display.theme")
+            .unwrap_or_else(|_| DEFAULT_THEME.to_string());
+
+        let settings = config
+            .get_table("theme")
+            .ok()
+            .and_then(|themes| themes.get(&name).cloned())
+            .map(|table| table.into_table().map_err(|e| e.to_string()))
+            .transpose()?;
+        let Some(mut settings) = settings else {
+            return Theme::builtin(&name).ok_or(format!("unknown theme '{name}'"));
+        };
+
+        let base = match settings.remove("base") {
+            Some(base) => string_value(base)?,
+            None => DEFAULT_THEME.to_string(),
+        };
+        let mut theme = Theme::builtin(&base).ok_or(format!("unknown theme '{base}'"))?;
+        theme.name = name;
+        theme.apply(settings)?;
+        Ok(theme)
+    }
+
+    fn apply(&mut self, settings: HashMap<String, Value>) -> Result<(), String> {
+        for (key, value) in settings {
+            let style = Style::parse(&string_value(value)?).map_err(|e| format!("{key}: {e}"))?;
+            match key.as_str() {
+                "status_bar" => self.status_bar = style,
+                "inactive_status_bar" => self.inactive_status_bar = style,
+                "message_bar" => self.message_bar = style,
+                "gutter" => self.gutter = style,
+                "selection" => self.selection = style,
+                _ => match Highlight::ALL.iter().find(|hl| hl.name() == key) {
+                    Some(hl) => self.highlights[*hl as usize] = style,
+                    None => return Err(format!("unknown setting '{key}'")),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+fn string_value(value: Value) -> Result<String, String> {
+    value.into_string().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::{File, FileFormat};
+
+    #[test]
+    fn parses_styles() {
+        assert_eq!(parse_color("dark_red"), Ok(Color::DarkRed));
+        assert_eq!(parse_color("208"), Ok(Color::AnsiValue(208)));
+        assert_eq!(
+            parse_color("#ff8000"),
+            Ok(Color::Rgb {
+                r: 255,
+                g: 128,
+                b: 0
+            })
+        );
+        assert!(parse_color("256").is_err());
+        assert!(parse_color("#ff80").is_err());
+
+        assert_eq!(
+            Style::parse("fg=white bg=#000080 bold underline"),
+            Ok(Style {
+                fg: Some(Color::White),
+                bg: Some(Color::Rgb { r: 0, g: 0, b: 128 }),
+                bold: true,
+                underline: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Style::parse("cyan italic"),
+            Ok(Style {
+                italic: true,
+                ..Style::fg(Color::Cyan)
+            })
+        );
+        assert_eq!(
+            Style::parse("blink"),
+            Err(String::from("bad colour 'blink'"))
+        );
+    }
+
+    #[test]
+    fn config_can_define_a_theme() {
+        let config = Config::builder()
+            .add_source(File::new("tests/themes", FileFormat::Ini))
+            .build()
+            .expect("failed to build config");
+        let theme = Theme::from_config(&config).unwrap();
+        assert_eq!(theme.name, "paper");
+        assert_eq!(
+            theme.highlight(Highlight::Comment),
+            Style {
+                italic: true,
+                ..Style::fg(Color::AnsiValue(245))
+            }
+        );
+        assert_eq!(theme.selection, Style::parse("bg=#ffffaf").unwrap());
+
+        // everything else comes from the base theme
+        let mono = Theme::builtin("mono").unwrap();
+        assert_eq!(
+            theme.highlight(Highlight::Keyword1),
+            mono.highlight(Highlight::Keyword1)
+        );
+        assert_eq!(theme.status_bar, mono.status_bar);
+
+        let config = Config::builder().build().unwrap();
+        assert_eq!(Theme::from_config(&config), Ok(Theme::default()));
+    }
+
+    #[test]
+    fn builtin_themes_style_every_highlight() {
+        let new_classes = [
+            Highlight::Constant,
+            Highlight::Builtin,
+            Highlight::Operator,
+            Highlight::Punctuation,
+            Highlight::Escape,
+            Highlight::DocComment,
+            Highlight::Todo,
+        ];
+        for name in ["default", "mono"] {
+            let theme = Theme::builtin(name).unwrap();
+            let normal = theme.highlight(Highlight::Normal);
+            for hl in Highlight::ALL
+                .into_iter()
+                .filter(|hl| *hl != Highlight::Normal)
+            {
+                assert_ne!(theme.highlight(hl), normal, "{name}: {}", hl.name());
+            }
+            for (i, a) in new_classes.iter().enumerate() {
+                for b in &new_classes[i + 1..] {
+                    assert_ne!(theme.highlight(*a), theme.highlight(*b), "{name}");
+                }
+            }
+        }
+    }
+}
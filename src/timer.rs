@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+
+/* things the editor does on its own once some time has passed */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timer {
+    StatusExpiry,
+    Autosave,
+    Swap,
+    DiskCheck,
+}
+
+/* the pending timers; scheduling one again moves its deadline */
+#[derive(Default)]
+pub struct TimerQueue {
+    timers: Vec<(Instant, Timer)>,
+}
+
+impl TimerQueue {
+    pub fn schedule(&mut self, timer: Timer, after: Duration) {
+        self.cancel(timer);
+        self.timers.push((Instant::now() + after, timer));
+    }
+
+    /* like `schedule`, but keeps the deadline of a timer that is already pending */
+    pub fn schedule_once(&mut self, timer: Timer, after: Duration) {
+        if !self.is_pending(timer) {
+            self.schedule(timer, after);
+        }
+    }
+
+    pub fn cancel(&mut self, timer: Timer) {
+        self.timers.retain(|(_, t)| *t != timer);
+    }
+
+    pub fn is_pending(&self, timer: Timer) -> bool {
+        self.timers.iter().any(|(_, t)| *t == timer)
+    }
+
+    /* how long until the next timer goes off, if any are pending */
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.timers
+            .iter()
+            .map(|(deadline, _)| deadline.saturating_duration_since(Instant::now()))
+            .min()
+    }
+
+    /* removes and returns the timers whose deadlines have passed, soonest first */
+    pub fn take_due(&mut self) -> Vec<Timer> {
+        let now = Instant::now();
+        let mut due = self
+            .timers
+            .iter()
+            .filter(|(deadline, _)| *deadline <= now)
+            .copied()
+            .collect::<Vec<_>>();
+        due.sort_by_key(|(deadline, _)| *deadline);
+        self.timers.retain(|(deadline, _)| *deadline > now);
+        due.into_iter().map(|(_, timer)| timer).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timers_go_off_in_order() {
+        let mut timers = TimerQueue::default();
+        assert_eq!(timers.next_timeout(), None);
+
+        timers.schedule(Timer::DiskCheck, Duration::from_secs(60));
+        timers.schedule(Timer::Swap, Duration::ZERO);
+        timers.schedule(Timer::StatusExpiry, Duration::ZERO);
+        timers.schedule_once(Timer::DiskCheck, Duration::ZERO);
+        assert_eq!(timers.next_timeout(), Some(Duration::ZERO));
+
+        assert_eq!(timers.take_due(), vec![Timer::Swap, Timer::StatusExpiry]);
+        assert!(timers.take_due().is_empty());
+        assert!(timers.is_pending(Timer::DiskCheck));
+        assert!(timers.next_timeout().unwrap() > Duration::from_secs(59));
+
+        timers.cancel(Timer::DiskCheck);
+        assert_eq!(timers.next_timeout(), None);
+    }
+}
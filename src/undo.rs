@@ -0,0 +1,267 @@
+use kilo_ed::*;
+
+// -----------------------------------------------------------------------------
+//     - Edits -
+// -----------------------------------------------------------------------------
+
+/* one reversible change; it carries its own text so it can be replayed or inverted */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    Insert { at: Position, text: String },
+    Delete { at: Position, text: String },
+    InsertRow { at: usize, text: String },
+    DeleteRow { at: usize, text: String },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::Insert { at, text } => Edit::Delete {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::Delete { at, text } => Edit::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::InsertRow { at, text } => Edit::DeleteRow {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::DeleteRow { at, text } => Edit::InsertRow {
+                at: *at,
+                text: text.clone(),
+            },
+        }
+    }
+
+    // a single typed (or backspaced) character that isn't a line break
+    fn typed_char(&self) -> Option<char> {
+        match self {
+            Edit::Insert { text, .. } | Edit::Delete { text, .. } => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '\n' => Some(c),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // returns true if `next` continues the run of typing that ends with `self`
+    fn continued_by(&self, next: &Edit) -> bool {
+        let (prev_c, next_c) = match (self.typed_char(), next.typed_char()) {
+            (Some(p), Some(n)) => (p, n),
+            _ => return false,
+        };
+
+        // start a new step at the beginning of each word
+        if prev_c.is_whitespace() && !next_c.is_whitespace() {
+            return false;
+        }
+
+        match (self, next) {
+            (Edit::Insert { at: p, text }, Edit::Insert { at: n, .. }) => {
+                p.y == n.y && p.x + text.len() == n.x
+            }
+            (Edit::Delete { at: p, .. }, Edit::Delete { at: n, text }) => {
+                p.y == n.y && n.x + text.len() == p.x
+            }
+            _ => false,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Undo History -
+// -----------------------------------------------------------------------------
+
+/* edits that are undone and redone together, with the cursor before and after */
+#[derive(Clone)]
+pub struct UndoStep {
+    pub edits: Vec<Edit>,
+    pub cursor_before: Position,
+    pub cursor_after: Position,
+}
+
+pub struct History {
+    undo: Vec<UndoStep>,
+    redo: Vec<UndoStep>,
+    saved: Option<usize>, // depth of the undo stack when the buffer was saved
+    sealed: bool,
+    group_depth: usize,
+    group_open: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            saved: Some(0),
+            sealed: false,
+            group_depth: 0,
+            group_open: false,
+        }
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, edit: Edit, cursor_before: Position, cursor_after: Position) {
+        self.redo.clear();
+        if matches!(self.saved, Some(depth) if depth > self.undo.len()) {
+            self.saved = None;
+        }
+
+        let at_saved = self.saved == Some(self.undo.len());
+        let merge = if self.group_depth > 0 {
+            self.group_open
+        } else {
+            !self.sealed
+                && !at_saved
+                && self
+                    .undo
+                    .last()
+                    .and_then(|step| step.edits.last())
+                    .is_some_and(|last| last.continued_by(&edit))
+        };
+
+        match self.undo.last_mut() {
+            Some(step) if merge => {
+                step.edits.push(edit);
+                step.cursor_after = cursor_after;
+            }
+            _ => self.undo.push(UndoStep {
+                edits: vec![edit],
+                cursor_before,
+                cursor_after,
+            }),
+        }
+
+        self.sealed = false;
+        if self.group_depth > 0 {
+            self.group_open = true;
+        }
+    }
+
+    /* forces the next edit to start a new undo step */
+    pub fn seal(&mut self) {
+        if self.group_depth == 0 {
+            self.sealed = true;
+        }
+    }
+
+    /* every edit until the matching end_group becomes one undo step */
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group_open = false;
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.sealed = true;
+        }
+    }
+
+    pub fn undo(&mut self) -> Option<UndoStep> {
+        let step = self.undo.pop()?;
+        self.redo.push(step.clone());
+        self.sealed = true;
+        Some(step)
+    }
+
+    pub fn redo(&mut self) -> Option<UndoStep> {
+        let step = self.redo.pop()?;
+        self.undo.push(step.clone());
+        self.sealed = true;
+        Some(step)
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+        self.sealed = true;
+    }
+
+    /* nothing in the history matches the file any more, e.g. after a format change */
+    pub fn forget_saved(&mut self) {
+        self.saved = None;
+        self.sealed = true;
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn typed(x: usize, c: char) -> Edit {
+        Edit::Insert {
+            at: Position { x, y: 0 },
+            text: c.to_string(),
+        }
+    }
+
+    #[test]
+    fn typing_a_word_is_one_step() {
+        let mut history = History::new();
+        for (x, c) in "abc".chars().enumerate() {
+            history.record(typed(x, c), Position::default(), Position::default());
+        }
+        assert_eq!(history.undo().map(|s| s.edits.len()), Some(3));
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn new_word_starts_new_step() {
+        let mut history = History::new();
+        for (x, c) in "ab cd".chars().enumerate() {
+            history.record(typed(x, c), Position::default(), Position::default());
+        }
+        assert_eq!(history.undo().map(|s| s.edits.len()), Some(2));
+        assert_eq!(history.undo().map(|s| s.edits.len()), Some(3));
+    }
+
+    #[test]
+    fn sealing_breaks_the_run() {
+        let mut history = History::new();
+        history.record(typed(0, 'a'), Position::default(), Position::default());
+        history.seal();
+        history.record(typed(1, 'b'), Position::default(), Position::default());
+        assert_eq!(history.undo().map(|s| s.edits.len()), Some(1));
+    }
+
+    #[test]
+    fn undo_back_to_save_point() {
+        let mut history = History::new();
+        history.record(typed(0, 'a'), Position::default(), Position::default());
+        history.mark_saved();
+        history.record(typed(1, 'b'), Position::default(), Position::default());
+        assert!(!history.is_saved());
+        history.undo();
+        assert!(history.is_saved());
+        history.undo();
+        assert!(!history.is_saved());
+        history.redo();
+        assert!(history.is_saved());
+    }
+
+    #[test]
+    fn forgotten_save_point_is_never_reached() {
+        let mut history = History::new();
+        history.forget_saved();
+        history.record(typed(0, 'a'), Position::default(), Position::default());
+        history.undo();
+        assert!(!history.is_saved());
+    }
+}
@@ -0,0 +1,403 @@
+use kilo_ed::*;
+
+// -----------------------------------------------------------------------------
+//     - Window -
+// -----------------------------------------------------------------------------
+
+/* one view onto a buffer; the focused window's state lives in Editor instead */
+#[derive(Default, Copy, Clone)]
+pub struct Window {
+    pub buffer: usize,
+    pub cursor: Position,
+    pub mark: Option<Position>,
+    pub rowoff: usize,
+    pub coloff: usize,
+}
+
+/* a region of the terminal, in columns and rows */
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    fn right(&self) -> u16 {
+        self.left + self.width
+    }
+
+    fn bottom(&self) -> u16 {
+        self.top + self.height
+    }
+}
+
+/* the smallest a window may be made */
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct Size {
+    pub width: u16,
+    pub height: u16,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// -----------------------------------------------------------------------------
+//     - Layout -
+// -----------------------------------------------------------------------------
+const MIN_SHARE: u16 = 10;
+const MAX_SHARE: u16 = 90;
+
+/* how the text area is split; share is the percentage given to first */
+pub enum Layout {
+    Window(usize),
+    Split {
+        vertical: bool,
+        share: u16,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /* replaces window `id` with a split showing it and `new_id` */
+    pub fn split(&mut self, id: usize, new_id: usize, vertical: bool) {
+        match self {
+            Layout::Window(w) if *w == id => {
+                *self = Layout::Split {
+                    vertical,
+                    share: 50,
+                    first: Box::new(Layout::Window(id)),
+                    second: Box::new(Layout::Window(new_id)),
+                };
+            }
+            Layout::Window(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.split(id, new_id, vertical);
+                second.split(id, new_id, vertical);
+            }
+        }
+    }
+
+    /* removes window `id`, giving its space to its sibling; ids above it shift down */
+    pub fn remove(&mut self, id: usize) {
+        self.detach(id);
+        self.renumber(id);
+    }
+
+    fn detach(&mut self, id: usize) {
+        if let Layout::Split { first, second, .. } = self {
+            if matches!(**first, Layout::Window(w) if w == id) {
+                *self = std::mem::replace(&mut **second, Layout::Window(0));
+            } else if matches!(**second, Layout::Window(w) if w == id) {
+                *self = std::mem::replace(&mut **first, Layout::Window(0));
+            } else {
+                first.detach(id);
+                second.detach(id);
+            }
+        }
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Layout::Window(w) if *w > removed => *w -= 1,
+            Layout::Window(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    /*
+     * grows (or shrinks, for a negative `delta`) window `id` within its split in
+     * `area`, returning false if no window can be kept at least `min` in size
+     */
+    pub fn resize(&mut self, id: usize, delta: i16, area: Rect, min: Size) -> bool {
+        let Layout::Split {
+            vertical,
+            share,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+
+        let in_first = matches!(**first, Layout::Window(w) if w == id);
+        let in_second = matches!(**second, Layout::Window(w) if w == id);
+        let (first_min, second_min) = (first.min_size(min), second.min_size(min));
+        if !(in_first || in_second) {
+            let (first_area, second_area) = divide(area, *vertical, *share, first_min, second_min);
+            return first.resize(id, delta, first_area, min)
+                || second.resize(id, delta, second_area, min);
+        }
+
+        let (space, first_min, second_min) = if *vertical {
+            (
+                area.width.saturating_sub(1),
+                first_min.width,
+                second_min.width,
+            )
+        } else {
+            (area.height, first_min.height, second_min.height)
+        };
+        if space == 0 {
+            return false;
+        }
+        // the range of shares that leaves both halves their minimum size
+        let lowest = (first_min as u32 * 100)
+            .div_ceil(space as u32)
+            .max(MIN_SHARE as u32);
+        let highest =
+            (space.saturating_sub(second_min) as u32 * 100 / space as u32).min(MAX_SHARE as u32);
+        if lowest > highest {
+            return false;
+        }
+
+        let delta = if in_first { delta } else { -delta };
+        let resized = (*share as i32 + delta as i32).clamp(lowest as i32, highest as i32) as u16;
+        let changed = resized != *share;
+        *share = resized;
+        changed
+    }
+
+    /* the smallest area that fits every window in this layout at `min` size */
+    fn min_size(&self, min: Size) -> Size {
+        match self {
+            Layout::Window(_) => min,
+            Layout::Split {
+                vertical,
+                first,
+                second,
+                ..
+            } => {
+                let (first, second) = (first.min_size(min), second.min_size(min));
+                if *vertical {
+                    Size {
+                        width: first.width + 1 + second.width,
+                        height: first.height.max(second.height),
+                    }
+                } else {
+                    Size {
+                        width: first.width.max(second.width),
+                        height: first.height + second.height,
+                    }
+                }
+            }
+        }
+    }
+
+    /* window ids in display order */
+    pub fn windows(&self) -> Vec<usize> {
+        match self {
+            Layout::Window(w) => vec![*w],
+            Layout::Split { first, second, .. } => {
+                let mut ids = first.windows();
+                ids.extend(second.windows());
+                ids
+            }
+        }
+    }
+
+    /*
+     * where each window goes within `area`, plus the columns between vertical
+     * splits; windows are kept at least `min` in size while there's room
+     */
+    pub fn arrange(
+        &self,
+        area: Rect,
+        min: Size,
+        windows: &mut Vec<(usize, Rect)>,
+        separators: &mut Vec<Rect>,
+    ) {
+        match self {
+            Layout::Window(w) => windows.push((*w, area)),
+            Layout::Split {
+                vertical,
+                share,
+                first,
+                second,
+            } => {
+                let (first_area, second_area) = divide(
+                    area,
+                    *vertical,
+                    *share,
+                    first.min_size(min),
+                    second.min_size(min),
+                );
+                first.arrange(first_area, min, windows, separators);
+                if *vertical {
+                    separators.push(Rect {
+                        left: first_area.right(),
+                        width: 1,
+                        ..area
+                    });
+                }
+                second.arrange(second_area, min, windows, separators);
+            }
+        }
+    }
+}
+
+/* splits `area` by `share` percent, moving the line so each half gets its minimum if it fits */
+fn divide(area: Rect, vertical: bool, share: u16, first: Size, second: Size) -> (Rect, Rect) {
+    let along = |space: u16, first: u16, second: u16| {
+        ((space as u32 * share as u32 / 100) as u16)
+            .min(space.saturating_sub(second))
+            .max(first.min(space))
+    };
+    if vertical {
+        let avail = area.width.saturating_sub(1);
+        let width = along(avail, first.width, second.width);
+        let left = Rect { width, ..area };
+        let right = Rect {
+            left: area.left + width + 1,
+            width: avail - width,
+            ..area
+        };
+        (left, right)
+    } else {
+        let height = along(area.height, first.height, second.height);
+        let top = Rect { height, ..area };
+        let bottom = Rect {
+            top: top.bottom(),
+            height: area.height - height,
+            ..area
+        };
+        (top, bottom)
+    }
+}
+
+/* the window next to `from` in the given direction, if there is one */
+pub fn neighbour(windows: &[(usize, Rect)], from: Rect, direction: Direction) -> Option<usize> {
+    let overlaps = |a1: u16, a2: u16, b1: u16, b2: u16| a1 < b2 && b1 < a2;
+
+    windows
+        .iter()
+        .filter_map(|(id, r)| {
+            let distance = match direction {
+                Direction::Left if r.right() <= from.left => from.left - r.right(),
+                Direction::Right if r.left >= from.right() => r.left - from.right(),
+                Direction::Up if r.bottom() <= from.top => from.top - r.bottom(),
+                Direction::Down if r.top >= from.bottom() => r.top - from.bottom(),
+                _ => return None,
+            };
+            let lined_up = match direction {
+                Direction::Left | Direction::Right => {
+                    overlaps(r.top, r.bottom(), from.top, from.bottom())
+                }
+                Direction::Up | Direction::Down => {
+                    overlaps(r.left, r.right(), from.left, from.right())
+                }
+            };
+            lined_up.then_some((distance, *id))
+        })
+        .min()
+        .map(|(_, id)| id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const AREA: Rect = Rect {
+        left: 0,
+        top: 0,
+        width: 81,
+        height: 40,
+    };
+
+    const MIN: Size = Size {
+        width: 27,
+        height: 4,
+    };
+
+    fn arrange(layout: &Layout) -> Vec<(usize, Rect)> {
+        let mut windows = Vec::new();
+        layout.arrange(AREA, MIN, &mut windows, &mut Vec::new());
+        windows
+    }
+
+    #[test]
+    fn splits_divide_the_area() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, true);
+        layout.split(1, 2, false);
+        let windows = arrange(&layout);
+        assert_eq!(layout.windows(), vec![0, 1, 2]);
+        assert_eq!(windows[0].1.width, 40);
+        assert_eq!(windows[1].1.left, 41);
+        assert_eq!(windows[1].1.height, 20);
+        assert_eq!(windows[2].1.top, 20);
+    }
+
+    #[test]
+    fn neighbours_follow_direction() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, true);
+        layout.split(1, 2, false);
+        let windows = arrange(&layout);
+        assert_eq!(neighbour(&windows, windows[0].1, Direction::Right), Some(1));
+        assert_eq!(neighbour(&windows, windows[2].1, Direction::Up), Some(1));
+        assert_eq!(neighbour(&windows, windows[2].1, Direction::Left), Some(0));
+        assert_eq!(neighbour(&windows, windows[0].1, Direction::Up), None);
+    }
+
+    #[test]
+    fn remove_and_resize() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, false);
+        layout.split(0, 2, true);
+        assert!(layout.resize(1, 10, AREA, MIN));
+        assert_eq!(
+            arrange(&layout)[2],
+            (
+                1,
+                Rect {
+                    top: 16,
+                    height: 24,
+                    ..AREA
+                }
+            )
+        );
+
+        layout.remove(0);
+        assert_eq!(layout.windows(), vec![1, 0]);
+        layout.remove(0);
+        assert_eq!(layout.windows(), vec![0]);
+        assert_eq!(arrange(&layout)[0].1, AREA);
+    }
+
+    #[test]
+    fn shrinking_stops_at_the_minimum_size() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, true);
+        let mut shrunk = 0;
+        while layout.resize(0, -5, AREA, MIN) {
+            shrunk += 1;
+            assert!(shrunk < 20);
+        }
+        assert_eq!(arrange(&layout)[0].1.width, MIN.width);
+        while layout.resize(0, 5, AREA, MIN) {}
+        assert!((MIN.width..MIN.width + 2).contains(&arrange(&layout)[1].1.width));
+
+        // a terminal too small for the minimum still splits without overflowing
+        let tiny = Rect {
+            width: 30,
+            height: 3,
+            ..AREA
+        };
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        layout.arrange(tiny, MIN, &mut windows, &mut separators);
+        assert_eq!(windows[0].1.width + 1 + windows[1].1.width, tiny.width);
+        assert!(!layout.resize(0, -5, tiny, MIN));
+    }
+}